@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS revoked_tokens_notify ON revoked_tokens;
+DROP FUNCTION IF EXISTS notify_token_revoked();
+DROP TABLE IF EXISTS revoked_tokens
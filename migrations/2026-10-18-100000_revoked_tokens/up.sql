@@ -0,0 +1,18 @@
+CREATE TABLE revoked_tokens (
+    jti VARCHAR(64) PRIMARY KEY,
+    expires TIMESTAMP NOT NULL
+);
+
+CREATE INDEX revoked_tokens_expires ON revoked_tokens (expires);
+
+-- Every instance listens on `token_revoked`, the payload is "<jti> <expiry as unix seconds>"
+CREATE FUNCTION notify_token_revoked() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('token_revoked', NEW.jti || ' ' || EXTRACT(EPOCH FROM NEW.expires)::BIGINT);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER revoked_tokens_notify
+    AFTER INSERT ON revoked_tokens
+    FOR EACH ROW EXECUTE PROCEDURE notify_token_revoked();
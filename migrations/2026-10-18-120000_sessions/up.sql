@@ -0,0 +1,16 @@
+-- A session is one refresh token family, the id is shared with `refresh_tokens.family`
+CREATE TABLE sessions (
+    id VARCHAR(64) PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    user_agent VARCHAR(512),
+    ip VARCHAR(64),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMP NOT NULL DEFAULT now(),
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user ON sessions (user_id);
@@ -0,0 +1,25 @@
+CREATE TABLE user_mfa (
+    user_id BIGINT PRIMARY KEY,
+    -- Base32, the authenticator app needs the secret itself so it can't be hashed
+    secret VARCHAR(64) NOT NULL,
+    confirmed BOOLEAN NOT NULL DEFAULT FALSE,
+    -- The last accepted TOTP time step, so a code can't be replayed
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE TABLE recovery_codes (
+    code_hash VARCHAR(64) PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    used BOOLEAN NOT NULL DEFAULT FALSE,
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX recovery_codes_user ON recovery_codes (user_id);
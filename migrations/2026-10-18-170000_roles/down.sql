@@ -0,0 +1,5 @@
+ALTER TABLE users ADD COLUMN level INTEGER NOT NULL DEFAULT 0;
+UPDATE users SET level = 1 WHERE role = 'admin';
+ALTER TABLE users DROP COLUMN role;
+DROP TABLE IF EXISTS role_permissions;
+DROP TABLE IF EXISTS roles
@@ -0,0 +1,33 @@
+CREATE TABLE roles (
+    name VARCHAR(32) PRIMARY KEY
+);
+
+CREATE TABLE role_permissions (
+    role VARCHAR(32) NOT NULL,
+    permission VARCHAR(64) NOT NULL,
+
+    PRIMARY KEY (role, permission),
+
+    FOREIGN KEY (role)
+        REFERENCES roles(name)
+        ON DELETE CASCADE
+);
+
+INSERT INTO roles (name) VALUES ('user'), ('support'), ('admin');
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('support', 'roles:read'),
+    ('admin', 'roles:read'),
+    ('admin', 'roles:assign'),
+    ('admin', 'users:delete');
+
+ALTER TABLE users ADD COLUMN role VARCHAR(32) NOT NULL DEFAULT 'user';
+
+ALTER TABLE users ADD FOREIGN KEY (role)
+    REFERENCES roles(name)
+    ON UPDATE CASCADE;
+
+-- A positive level let an account delete others, which is the only thing levels were ever used for
+UPDATE users SET role = 'admin' WHERE level >= 1;
+
+ALTER TABLE users DROP COLUMN level;
@@ -0,0 +1,3 @@
+DELETE FROM role_permissions WHERE permission IN ('users:read', 'users:suspend', 'users:reset_password');
+DROP TABLE IF EXISTS audit_log;
+ALTER TABLE users DROP COLUMN IF EXISTS suspended_at
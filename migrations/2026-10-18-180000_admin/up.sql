@@ -0,0 +1,25 @@
+ALTER TABLE users ADD COLUMN suspended_at TIMESTAMP;
+
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    -- NULL once the acting account is gone, the entry itself is kept
+    actor_id BIGINT,
+    action VARCHAR(64) NOT NULL,
+    -- Deliberately no foreign key, entries about deleted users must survive them
+    target_user_id BIGINT,
+    details TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (actor_id)
+        REFERENCES users(id)
+        ON DELETE SET NULL
+);
+
+CREATE INDEX audit_log_target ON audit_log (target_user_id);
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('support', 'users:read'),
+    ('support', 'users:reset_password'),
+    ('admin', 'users:read'),
+    ('admin', 'users:suspend'),
+    ('admin', 'users:reset_password');
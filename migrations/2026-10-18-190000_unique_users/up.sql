@@ -0,0 +1,35 @@
+-- Lists every group of accounts that would collide once case is ignored, and refuses to go on
+-- while any exist, so that no account is silently renamed or merged
+DO $$
+DECLARE
+    duplicate RECORD;
+    found BOOLEAN := FALSE;
+BEGIN
+    FOR duplicate IN
+        SELECT lower(username) AS value, string_agg(id::TEXT, ', ' ORDER BY id) AS ids
+        FROM users
+        GROUP BY lower(username)
+        HAVING count(*) > 1
+    LOOP
+        found := TRUE;
+        RAISE NOTICE 'Duplicate username "%" held by users %', duplicate.value, duplicate.ids;
+    END LOOP;
+
+    FOR duplicate IN
+        SELECT lower(email) AS value, string_agg(id::TEXT, ', ' ORDER BY id) AS ids
+        FROM users
+        GROUP BY lower(email)
+        HAVING count(*) > 1
+    LOOP
+        found := TRUE;
+        RAISE NOTICE 'Duplicate email "%" held by users %', duplicate.value, duplicate.ids;
+    END LOOP;
+
+    IF found THEN
+        RAISE EXCEPTION 'Resolve the duplicate accounts listed above before running this migration';
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX users_username_lower ON users (lower(username));
+CREATE UNIQUE INDEX users_email_lower ON users (lower(email));
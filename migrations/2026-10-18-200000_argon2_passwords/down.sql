@@ -0,0 +1,2 @@
+-- Only safe while every stored hash is still bcrypt
+ALTER TABLE users ALTER COLUMN password TYPE VARCHAR(64)
\ No newline at end of file
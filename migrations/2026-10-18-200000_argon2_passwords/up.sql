@@ -0,0 +1,2 @@
+-- Argon2id PHC strings are longer than the old bcrypt hashes
+ALTER TABLE users ALTER COLUMN password TYPE VARCHAR(255);
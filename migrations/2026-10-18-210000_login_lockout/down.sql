@@ -0,0 +1,3 @@
+DELETE FROM role_permissions WHERE permission = 'users:unlock';
+ALTER TABLE users DROP COLUMN IF EXISTS locked_until;
+ALTER TABLE users DROP COLUMN IF EXISTS failed_logins
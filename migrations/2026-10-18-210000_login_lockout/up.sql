@@ -0,0 +1,6 @@
+ALTER TABLE users ADD COLUMN failed_logins INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMP;
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('support', 'users:unlock'),
+    ('admin', 'users:unlock');
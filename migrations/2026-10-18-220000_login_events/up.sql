@@ -0,0 +1,18 @@
+CREATE TABLE login_events (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    success BOOLEAN NOT NULL,
+    -- Why the attempt failed, e.g. "password", "mfa" or "locked"
+    failure VARCHAR(32),
+    ip VARCHAR(64),
+    -- The /24 (IPv4) or /48 (IPv6) the address belongs to
+    network VARCHAR(64),
+    user_agent VARCHAR(512),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX login_events_user ON login_events (user_id, created_at);
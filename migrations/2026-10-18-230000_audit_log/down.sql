@@ -0,0 +1,10 @@
+DELETE FROM role_permissions WHERE permission = 'audit:read';
+DROP TRIGGER IF EXISTS audit_log_no_truncate ON audit_log;
+DROP TRIGGER IF EXISTS audit_log_no_changes ON audit_log;
+DROP FUNCTION IF EXISTS audit_log_append_only();
+DROP INDEX IF EXISTS audit_log_created;
+DROP INDEX IF EXISTS audit_log_contact;
+DROP INDEX IF EXISTS audit_log_actor;
+ALTER TABLE audit_log DROP COLUMN IF EXISTS after_state;
+ALTER TABLE audit_log DROP COLUMN IF EXISTS before_state;
+ALTER TABLE audit_log DROP COLUMN IF EXISTS target_contact_id
@@ -0,0 +1,28 @@
+-- Entries must outlive their actors, and with the trigger below nothing may rewrite them to NULL either
+ALTER TABLE audit_log DROP CONSTRAINT audit_log_actor_id_fkey;
+
+ALTER TABLE audit_log ADD COLUMN target_contact_id BIGINT;
+-- JSON summaries of the affected record before and after the action
+ALTER TABLE audit_log ADD COLUMN before_state TEXT;
+ALTER TABLE audit_log ADD COLUMN after_state TEXT;
+
+CREATE INDEX audit_log_actor ON audit_log (actor_id);
+CREATE INDEX audit_log_contact ON audit_log (target_contact_id);
+CREATE INDEX audit_log_created ON audit_log (created_at);
+
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_changes
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE PROCEDURE audit_log_append_only();
+
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE PROCEDURE audit_log_append_only();
+
+INSERT INTO role_permissions (role, permission) VALUES
+    ('admin', 'audit:read');
@@ -0,0 +1,29 @@
+CREATE TABLE share_links (
+    -- The `jti` of the link token
+    id VARCHAR(64) PRIMARY KEY,
+    contact_id BIGINT NOT NULL,
+    creator BIGINT NOT NULL,
+    -- NULL means anybody holding the link can accept it
+    recipient_id BIGINT,
+    -- NULL means the link can be used until it expires
+    max_uses INTEGER,
+    uses INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires TIMESTAMP NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+
+    FOREIGN KEY (contact_id)
+        REFERENCES contacts(id)
+        ON DELETE CASCADE,
+
+    FOREIGN KEY (creator)
+        REFERENCES users(id)
+        ON DELETE CASCADE,
+
+    FOREIGN KEY (recipient_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX share_links_creator ON share_links (creator);
+CREATE INDEX share_links_contact ON share_links (contact_id);
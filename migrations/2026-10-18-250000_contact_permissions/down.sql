@@ -0,0 +1 @@
+ALTER TABLE users_contacts_join DROP COLUMN permission
\ No newline at end of file
@@ -0,0 +1,12 @@
+ALTER TABLE users_contacts_join
+    ADD COLUMN permission SMALLINT NOT NULL DEFAULT 0 CHECK(permission IN (0, 1, 2));
+    -- 0 = Viewer
+    -- 1 = Editor
+    -- 2 = Co-owner
+
+-- Until now only creators were related to their contacts directly
+UPDATE users_contacts_join
+    SET permission = 2
+    FROM contacts
+    WHERE contacts.id = users_contacts_join.contact_id
+        AND contacts.creator = users_contacts_join.user_id;
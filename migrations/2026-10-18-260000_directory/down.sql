@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS contacts_visibility;
+DROP TABLE IF EXISTS directory_hidden_keys
\ No newline at end of file
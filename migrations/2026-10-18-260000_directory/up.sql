@@ -0,0 +1,13 @@
+-- Info keys of a public contact that the directory leaves out
+CREATE TABLE directory_hidden_keys (
+    contact_id BIGINT NOT NULL,
+    key VARCHAR(64) NOT NULL,
+
+    PRIMARY KEY (contact_id, key),
+
+    FOREIGN KEY (contact_id)
+        REFERENCES contacts(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX contacts_visibility ON contacts (visibility);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS persona_copies;
+DROP TABLE IF EXISTS persona_tokens;
+DROP TABLE IF EXISTS persona_info;
+DROP TABLE IF EXISTS personas
\ No newline at end of file
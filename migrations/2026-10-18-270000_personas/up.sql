@@ -0,0 +1,72 @@
+CREATE TABLE personas (
+    id BIGSERIAL PRIMARY KEY,
+    -- Private personas are only ever shared with a named recipient
+    private BOOLEAN NOT NULL DEFAULT TRUE,
+    user_id BIGINT NOT NULL,
+    -- What the owner calls it, e.g. "work" or "personal"
+    label VARCHAR(64) NOT NULL,
+    -- What recipients see as the contact's name
+    name VARCHAR(64) NOT NULL,
+    icon BYTEA,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX personas_user_label ON personas (user_id, lower(label));
+
+CREATE TABLE persona_info (
+    key VARCHAR(64) NOT NULL,
+    value VARCHAR(512) NOT NULL,
+    persona_id BIGINT NOT NULL,
+
+    PRIMARY KEY (key, value, persona_id),
+
+    FOREIGN KEY (persona_id)
+        REFERENCES personas(id)
+        ON DELETE CASCADE
+);
+
+CREATE TABLE persona_tokens (
+    -- The `jti` of the persona token
+    id VARCHAR(64) PRIMARY KEY,
+    persona_id BIGINT NOT NULL,
+    -- NULL means anybody holding the token can open it
+    recipient_id BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    expires TIMESTAMP NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+
+    FOREIGN KEY (persona_id)
+        REFERENCES personas(id)
+        ON DELETE CASCADE,
+
+    FOREIGN KEY (recipient_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX persona_tokens_persona ON persona_tokens (persona_id);
+
+-- The contact a recipient's copy of a persona became, so accepting it again doesn't make another one
+CREATE TABLE persona_copies (
+    user_id BIGINT NOT NULL,
+    persona_id BIGINT NOT NULL,
+    contact_id BIGINT NOT NULL,
+
+    PRIMARY KEY (user_id, persona_id),
+
+    FOREIGN KEY (user_id)
+        REFERENCES users(id)
+        ON DELETE CASCADE,
+
+    FOREIGN KEY (persona_id)
+        REFERENCES personas(id)
+        ON DELETE CASCADE,
+
+    FOREIGN KEY (contact_id)
+        REFERENCES contacts(id)
+        ON DELETE CASCADE
+);
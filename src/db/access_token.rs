@@ -0,0 +1,97 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, QueryDsl, RunQueryDsl, dsl::{IntervalDsl, now}};
+use serde::Serialize;
+
+use super::{DefaultConnection, schema::access_tokens};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct AccessToken {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: NaiveDateTime,
+    pub expires: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub revoked: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="access_tokens"]
+pub struct NewAccessToken {
+    pub user_id: i64,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires: Option<NaiveDateTime>,
+}
+
+impl AccessToken {
+
+    pub fn query_by_hash (hash: &str, db: &DefaultConnection) -> Result<AccessToken, diesel::result::Error> {
+        access_tokens::table
+            .filter(access_tokens::token_hash.eq(hash))
+            .first::<AccessToken>(db)
+    }
+
+    pub fn is_valid (&self) -> bool {
+        !self.revoked && self.expires
+            .map(|expires| expires > chrono::Utc::now().naive_utc())
+            .unwrap_or(true)
+    }
+
+    pub fn has_scope (&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub fn of_user (user: UserId, db: &DefaultConnection) -> Result<Vec<AccessToken>, diesel::result::Error> {
+        access_tokens::table
+            .filter(access_tokens::user_id.eq(*user)
+                .and(access_tokens::revoked.eq(false)))
+            .order(access_tokens::created_at.desc())
+            .load::<AccessToken>(db)
+    }
+
+    // Same as sessions, last use is only recorded once a minute
+    pub fn touch (id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(access_tokens::table
+                .filter(access_tokens::id.eq(id)
+                    .and(access_tokens::last_used_at.is_null()
+                        .or(access_tokens::last_used_at.lt((now - 1.minute()).nullable())))))
+            .set(access_tokens::last_used_at.eq(now))
+            .execute(db)
+    }
+
+    pub fn revoke (user: UserId, id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let revoked = diesel::update(access_tokens::table
+                .filter(access_tokens::id.eq(id)
+                    .and(access_tokens::user_id.eq(*user))
+                    .and(access_tokens::revoked.eq(false))))
+            .set(access_tokens::revoked.eq(true))
+            .execute(db)?;
+
+        if revoked == 0 {
+            return Err(diesel::result::Error::NotFound)
+        }
+
+        Ok(revoked)
+    }
+
+    // A new password locks out every integration along with the sessions
+    pub fn revoke_all (user: UserId, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(access_tokens::table
+                .filter(access_tokens::user_id.eq(*user)
+                    .and(access_tokens::revoked.eq(false))))
+            .set(access_tokens::revoked.eq(true))
+            .execute(db)
+    }
+
+}
+
+impl_register_for!(NewAccessToken, AccessToken, access_tokens::table);
@@ -0,0 +1,101 @@
+use chrono::NaiveDateTime;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use super::{DefaultConnection, Register, schema::audit_log};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+pub const PAGE_SIZE: i64 = 100;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    pub action: String,
+    pub target_user_id: Option<i64>,
+    pub details: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub target_contact_id: Option<i64>,
+    pub before_state: Option<String>,
+    pub after_state: Option<String>,
+}
+
+#[derive(Insertable, Default, Clone, Debug)]
+#[table_name="audit_log"]
+pub struct NewAuditEntry {
+    pub actor_id: Option<i64>,
+    pub action: String,
+    pub target_user_id: Option<i64>,
+    pub details: Option<String>,
+    pub target_contact_id: Option<i64>,
+    pub before_state: Option<String>,
+    pub after_state: Option<String>,
+}
+
+// Records are kept as JSON text, failing to serialize one only loses the summary
+pub fn summary<T: Serialize> (value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct AuditFilter {
+    pub actor: Option<i64>,
+    pub user: Option<i64>,
+    pub contact: Option<i64>,
+    pub action: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl AuditEntry {
+
+    pub fn record (actor: UserId, action: &str, target: Option<i64>, details: Option<String>, db: &DefaultConnection) -> Result<AuditEntry, diesel::result::Error> {
+        NewAuditEntry {
+            actor_id: Some(*actor),
+            action: action.to_string(),
+            target_user_id: target,
+            details,
+            ..Default::default()
+        }.register(db)
+    }
+
+    pub fn about (target: i64, db: &DefaultConnection) -> Result<Vec<AuditEntry>, diesel::result::Error> {
+        audit_log::table
+            .filter(audit_log::target_user_id.eq(target))
+            .order(audit_log::created_at.desc())
+            .load::<AuditEntry>(db)
+    }
+
+    pub fn query (filter: &AuditFilter, page: i64, db: &DefaultConnection) -> Result<Vec<AuditEntry>, diesel::result::Error> {
+        let mut query = audit_log::table.into_boxed();
+
+        if let Some(actor) = filter.actor {
+            query = query.filter(audit_log::actor_id.eq(actor));
+        }
+        if let Some(user) = filter.user {
+            query = query.filter(audit_log::target_user_id.eq(user));
+        }
+        if let Some(contact) = filter.contact {
+            query = query.filter(audit_log::target_contact_id.eq(contact));
+        }
+        if let Some(action) = &filter.action {
+            query = query.filter(audit_log::action.eq(action));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(audit_log::created_at.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(audit_log::created_at.lt(until));
+        }
+
+        query
+            .order(audit_log::id.desc())
+            .offset(page.max(0) * PAGE_SIZE)
+            .limit(PAGE_SIZE)
+            .load::<AuditEntry>(db)
+    }
+
+}
+
+impl_register_for!(NewAuditEntry, AuditEntry, audit_log::table);
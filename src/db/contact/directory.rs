@@ -0,0 +1,138 @@
+use std::collections::{HashMap, HashSet};
+
+use diesel::{BoolExpressionMethods, ExpressionMethods, JoinOnDsl, PgTextExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use crate::db::DefaultConnection;
+use crate::db::schema::{contacts, directory_hidden_keys, info};
+use super::{Contact, Visibility, info::{BareInfo, InfoFragment}};
+
+pub const PAGE_SIZE: i64 = 50;
+// Anything shorter would match most of the directory
+pub const MIN_QUERY_CHARS: usize = 3;
+
+// Besides the name, a search only looks at these keys
+pub const SEARCH_KEYS: &[&str] = &["email", "phone", "organization", "city"];
+
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name="directory_hidden_keys"]
+pub struct HiddenKey {
+    pub contact_id: i64,
+    pub key: String,
+}
+
+impl HiddenKey {
+
+    pub fn of_contact (contact: i64, db: &DefaultConnection) -> Result<Vec<String>, diesel::result::Error> {
+        directory_hidden_keys::table
+            .filter(directory_hidden_keys::contact_id.eq(contact))
+            .order(directory_hidden_keys::key.asc())
+            .select(directory_hidden_keys::key)
+            .load::<String>(db)
+    }
+
+    // The owner always sends the whole set
+    pub fn replace_for (contact: i64, keys: Vec<String>, db: &DefaultConnection) -> Result<Vec<String>, diesel::result::Error> {
+        diesel::delete(directory_hidden_keys::table
+                .filter(directory_hidden_keys::contact_id.eq(contact)))
+            .execute(db)?;
+
+        let keys = keys.into_iter()
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .map(|key| HiddenKey {
+                contact_id: contact,
+                key
+            })
+            .collect::<Vec<HiddenKey>>();
+
+        diesel::insert_into(directory_hidden_keys::table)
+            .values(&keys)
+            .execute(db)?;
+
+        HiddenKey::of_contact(contact, db)
+    }
+
+}
+
+// What everybody else gets to see of a public contact
+#[derive(Serialize, Clone, Debug)]
+pub struct DirectoryEntry {
+    pub id: i64,
+    pub name: String,
+    pub icon: Option<Vec<u8>>,
+    pub info: BareInfo,
+}
+
+impl DirectoryEntry {
+
+    pub fn of (contact: Contact, db: &DefaultConnection) -> Result<DirectoryEntry, diesel::result::Error> {
+        Ok(DirectoryEntry::of_all(vec![contact], db)?.remove(0))
+    }
+
+    // Hidden keys and info are loaded for the whole page at once, not contact by contact
+    pub fn of_all (contacts: Vec<Contact>, db: &DefaultConnection) -> Result<Vec<DirectoryEntry>, diesel::result::Error> {
+        let ids = contacts.iter()
+            .map(|contact| contact.id)
+            .collect::<Vec<i64>>();
+
+        let hidden = directory_hidden_keys::table
+            .filter(directory_hidden_keys::contact_id.eq_any(ids.clone()))
+            .load::<HiddenKey>(db)?
+            .into_iter()
+            .map(|hidden| (hidden.contact_id, hidden.key))
+            .collect::<HashSet<(i64, String)>>();
+
+        let mut infos = HashMap::<i64, BareInfo>::new();
+        for fragment in info::table.filter(info::contact_id.eq_any(ids)).load::<InfoFragment>(db)? {
+            if hidden.contains(&(fragment.contact_id, fragment.key.clone())) {
+                continue
+            }
+
+            infos.entry(fragment.contact_id)
+                .or_insert_with(BareInfo::new)
+                .entry(fragment.key)
+                .or_insert_with(Vec::new)
+                .push(fragment.value);
+        }
+
+        Ok(contacts.into_iter()
+            .map(|contact| DirectoryEntry {
+                info: infos.remove(&contact.id).unwrap_or_default(),
+                id: contact.id,
+                name: contact.name,
+                icon: contact.icon
+            })
+            .collect())
+    }
+
+    // Same escaping as the user search; hidden keys never match. Callers enforce `MIN_QUERY_CHARS`.
+    // Matching info stays a subquery, so common queries never round-trip every matching id.
+    pub fn search (query: &str, page: i64, db: &DefaultConnection) -> Result<Vec<DirectoryEntry>, diesel::result::Error> {
+        let pattern = format!("%{}%", query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_"));
+        let public = i16::from(Visibility::Public);
+
+        let by_info = info::table
+            .left_join(directory_hidden_keys::table.on(directory_hidden_keys::contact_id.eq(info::contact_id)
+                .and(directory_hidden_keys::key.eq(info::key))))
+            .filter(info::key.eq_any(SEARCH_KEYS.iter().map(|key| key.to_string()).collect::<Vec<String>>())
+                .and(info::value.ilike(&pattern))
+                .and(directory_hidden_keys::key.is_null()))
+            .select(info::contact_id);
+
+        let page = contacts::table
+            .filter(contacts::visibility.eq(public)
+                .and(contacts::name.ilike(&pattern)
+                    .or(contacts::id.eq_any(by_info))))
+            .order((contacts::name.asc(), contacts::id.asc()))
+            .offset(page.max(0) * PAGE_SIZE)
+            .limit(PAGE_SIZE)
+            .load::<Contact>(db)?;
+
+        DirectoryEntry::of_all(page, db)
+    }
+
+}
@@ -7,12 +7,12 @@ use serde::Serializer;
 use crate::{db::{DefaultConnection, Register, schema::info}, impl_register_for};
 
 use super::{IsContact};
-use crate::db::{Delete, ConjuctionTable};
+use crate::db::Delete;
 use diesel::result::Error;
 use crate::db::schema::info::dsl::key;
 use crate::db::schema::info::columns::contact_id;
 use crate::db::user::{ForUser, UserId};
-use crate::db::contact::{Contact, UserContactRelation};
+use crate::db::contact::{Contact, ContactPermission};
 
 #[derive(Queryable, Insertable, AsChangeset, Deserialize, Clone, Debug)]
 #[table_name="info"]
@@ -66,15 +66,16 @@ impl ForContact for InfoSection {
 pub struct Jurisdiction<V: ForContact> (Vec<V>);
 
 impl<V: ForContact> Jurisdiction<V> {
-    pub fn new (user: UserId, items: Vec<V>, db: &DefaultConnection) -> diesel::result::QueryResult<Jurisdiction<V>> {
+    pub fn new (user: UserId, items: Vec<V>, needed: ContactPermission, db: &DefaultConnection) -> diesel::result::QueryResult<Jurisdiction<V>> {
         let mut contacts = HashSet::<i64>::new();
+        let factory = ForUser::<Contact>::from(user);
         Ok(Jurisdiction(items.into_iter()
             .map(|item| {
                 let contact = item.contact_id();
                 if contacts.contains(&contact) {
                     Ok(item)
                 } else {
-                    UserContactRelation(user.0, contact).check_relation(db)?;
+                    factory.has_jurisdiction(contact, needed, db)?;
                     contacts.insert(contact);
                     Ok(item)
                 }
@@ -124,7 +125,7 @@ impl Delete for ForUser<InfoFragment> {
     type PrimaryKey = InfoFragment;
 
     fn delete(&self, db: &DefaultConnection, framgent: Self::PrimaryKey) -> Result<usize, Error> {
-        self.into::<Contact>().has_jurisdiction(framgent.contact_id, db)?;
+        self.into::<Contact>().has_jurisdiction(framgent.contact_id, ContactPermission::Editor, db)?;
         diesel::delete(info::table.find((framgent.key, framgent.value, framgent.contact_id))).execute(db)
     }
 }
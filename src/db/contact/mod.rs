@@ -8,12 +8,14 @@ use super::{ConjuctionTable, DefaultConnection, schema::{contacts, users_contact
 use crate::update;
 use crate::db::user::ForUser;
 use diesel::result::Error;
+use std::convert::TryFrom;
 use crate::db::schema::users;
 use crate::db::{Delete, Register};
 
 pub mod info;
+pub mod directory;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Visibility {
     Local, Private, Public
 }
@@ -28,12 +30,57 @@ impl From<Visibility> for i16 {
     }
 }
 
-impl From<i16> for Visibility {
+// Unknown values are handed back as the error
+impl TryFrom<i16> for Visibility {
+    type Error = i16;
+
+    fn try_from(i: i16) -> Result<Self, i16> {
+        match i {
+            0 => Ok(Visibility::Local),
+            1 => Ok(Visibility::Private),
+            2 => Ok(Visibility::Public),
+            _ => Err(i)
+        }
+    }
+}
+
+impl Visibility {
+
+    // Stored values are kept valid by the CHECK constraint, anything else gets the least exposure
+    fn stored(i: i16) -> Visibility {
+        Visibility::try_from(i).unwrap_or(Visibility::Local)
+    }
+
+    // Local contacts never leave their creator's account
+    pub fn can_share(self) -> bool {
+        self != Visibility::Local
+    }
+
+}
+
+// Ordered, each level can do everything the ones before it can
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContactPermission {
+    Viewer, Editor, CoOwner
+}
+
+impl From<ContactPermission> for i16 {
+    fn from(p: ContactPermission) -> Self {
+        match p {
+            ContactPermission::Viewer => 0,
+            ContactPermission::Editor => 1,
+            ContactPermission::CoOwner => 2
+        }
+    }
+}
+
+impl From<i16> for ContactPermission {
     fn from(i: i16) -> Self {
         match i {
-            0 => Visibility::Local,
-            1 => Visibility::Private,
-            _ => Visibility::Public
+            1 => ContactPermission::Editor,
+            2 => ContactPermission::CoOwner,
+            _ => ContactPermission::Viewer
         }
     }
 }
@@ -45,6 +92,14 @@ pub struct PostContact {
     visibility: i16,
 }
 
+impl PostContact {
+
+    pub fn visibility(&self) -> Result<Visibility, i16> {
+        Visibility::try_from(self.visibility)
+    }
+
+}
+
 impl ForUser<PostContact> {
     pub fn relate(&self, this: PostContact) -> NewContact {
         self.into::<NewContact>().new(
@@ -76,7 +131,8 @@ impl Register for NewContact {
 
         UserContactRelation (
             out.creator,
-            out.id
+            out.id,
+            ContactPermission::CoOwner.into()
         ).register(db)?;
 
         Ok(out)
@@ -86,7 +142,7 @@ impl Register for NewContact {
 impl NewContact {
 
     pub fn visibility(&self) -> Visibility {
-        self.visibility.into()
+        Visibility::stored(self.visibility)
     }
     pub fn set_visibility(&mut self, v: Visibility) {
         self.visibility = v.into()
@@ -118,13 +174,13 @@ pub struct UpdateContact {
     visibility: Option<i16>,
 }
 
+// Editing never hands the contact over to whoever made the edit
 #[derive(AsChangeset, Serialize, Deserialize, Clone, Debug)]
 #[table_name="contacts"]
 pub struct _UpdateContact {
     pub name: Option<String>,
     pub icon: Option<Option<Vec<u8>>>,
     visibility: Option<i16>,
-    pub creator: i64
 }
 
 impl ForUser<UpdateContact> {
@@ -137,16 +193,17 @@ impl ForUser<UpdateContact> {
             } => _UpdateContact {
                 name,
                 icon,
-                visibility: vis,
-                creator: self.0
+                visibility: vis
             }
         }
     }
 }
 
 impl UpdateContact {
-    pub fn visibility(&self) -> Option<Visibility> {
-        self.visibility.map(|v| v.into())
+    pub fn visibility(&self) -> Result<Option<Visibility>, i16> {
+        self.visibility
+            .map(Visibility::try_from)
+            .transpose()
     }
     pub fn set_visibility(&mut self, v: Option<Visibility>) {
         self.visibility = v.map(|v| v.into())
@@ -162,7 +219,7 @@ impl Delete for ForUser<Contact> {
     type PrimaryKey = i64;
 
     fn delete(&self, db: &DefaultConnection, id: Self::PrimaryKey) -> Result<usize, Error> {
-        self.has_jurisdiction(id, db)?;
+        self.has_jurisdiction(id, ContactPermission::CoOwner, db)?;
         diesel::delete(contacts::table)
             .filter(contacts::id.eq(id))
             .execute(db)
@@ -183,22 +240,47 @@ impl Contact {
         contacts::table.filter(contacts::id.eq(id))
             .first::<Contact> (db)
     }
+
+    // What any signed in user can look up in the directory
+    pub fn query_public(id: i64, db: &DefaultConnection) -> diesel::result::QueryResult<Contact> {
+        contacts::table.filter(contacts::id.eq(id)
+                .and(contacts::visibility.eq(i16::from(Visibility::Public))))
+            .first::<Contact> (db)
+    }
 }
 
 impl ForUser<Contact> {
     pub fn query_by_id (&self, id: i64, db: &DefaultConnection) -> diesel::result::QueryResult<Contact> {
+        self.query_for(id, ContactPermission::Viewer, db)
+    }
+
+    pub fn query_for (&self, id: i64, needed: ContactPermission, db: &DefaultConnection) -> diesel::result::QueryResult<Contact> {
         let contact = contacts::table.filter(contacts::id.eq(id))
             .first::<Contact> (db)?;
 
-        self.has_jurisdiction(contact.id, db)?;
+        self.has_jurisdiction(contact.id, needed, db)?;
 
         Ok(contact)
     }
 
-    pub fn has_jurisdiction (&self, id: i64, db: &DefaultConnection) -> diesel::result::QueryResult<UserContactRelation> {
+    pub fn permission (&self, id: i64, db: &DefaultConnection) -> diesel::result::QueryResult<ContactPermission> {
         users_contacts_join::table.filter(users_contacts_join::user_id.eq(self.0)
             .and(users_contacts_join::contact_id.eq(id)))
             .first::<UserContactRelation>(db)
+            .map(|relation| relation.permission())
+    }
+
+    // Too little access looks the same as none at all
+    pub fn has_jurisdiction (&self, id: i64, needed: ContactPermission, db: &DefaultConnection) -> diesel::result::QueryResult<UserContactRelation> {
+        let relation = users_contacts_join::table.filter(users_contacts_join::user_id.eq(self.0)
+            .and(users_contacts_join::contact_id.eq(id)))
+            .first::<UserContactRelation>(db)?;
+
+        if relation.permission() < needed {
+            return Err(Error::NotFound)
+        }
+
+        Ok(relation)
     }
 }
 
@@ -210,9 +292,56 @@ pub struct UserContactRelation(
     #[column_name = "user_id"]
     pub i64, 
     #[column_name = "contact_id"]
-    pub i64
+    pub i64,
+    #[column_name = "permission"]
+    pub i16
 );
 
+impl UserContactRelation {
+
+    pub fn permission(&self) -> ContactPermission {
+        self.2.into()
+    }
+
+    pub fn of_contact (contact: i64, db: &DefaultConnection) -> QueryResult<Vec<(UserContactRelation, User)>> {
+        users_contacts_join::table
+            .filter(users_contacts_join::contact_id.eq(contact))
+            .inner_join(users::table)
+            .order(users::username.asc())
+            .load::<(UserContactRelation, User)>(db)
+    }
+
+    // Sharing again with somebody who already has the contact changes their level
+    pub fn grant (self, db: &DefaultConnection) -> QueryResult<UserContactRelation> {
+        diesel::insert_into(users_contacts_join::table)
+            .values(self)
+            .on_conflict((users_contacts_join::user_id, users_contacts_join::contact_id))
+            .do_update()
+            .set(users_contacts_join::permission.eq(self.2))
+            .get_result::<UserContactRelation>(db)
+    }
+
+    pub fn count_of_contact (contact: i64, db: &DefaultConnection) -> QueryResult<i64> {
+        users_contacts_join::table
+            .filter(users_contacts_join::contact_id.eq(contact))
+            .count()
+            .get_result::<i64>(db)
+    }
+
+    pub fn revoke (user: i64, contact: i64, db: &DefaultConnection) -> QueryResult<usize> {
+        let revoked = diesel::delete(users_contacts_join::table
+                .find((user, contact)))
+            .execute(db)?;
+
+        if revoked == 0 {
+            return Err(Error::NotFound)
+        }
+
+        Ok(revoked)
+    }
+
+}
+
 impl ConjuctionTable for UserContactRelation {
     
     type A = User;
@@ -296,7 +425,7 @@ impl IsContact for Contact {
     }
 
     fn visibility(&self) -> Visibility {
-        self.visibility.into()
+        Visibility::stored(self.visibility)
     }
 
     fn set_visibility(&mut self, v: Visibility) {
@@ -307,4 +436,29 @@ impl IsContact for Contact {
 impl_register_for!(UserContactRelation, UserContactRelation, users_contacts_join::table);
 
 #[derive(Clone, Serialize, Deserialize)]
-pub struct ContactInfo (pub Contact);
\ No newline at end of file
+pub struct ContactInfo (pub Contact);
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn known_visibilities_round_trip() {
+        for visibility in [Visibility::Local, Visibility::Private, Visibility::Public].iter() {
+            assert_eq!(Visibility::try_from(i16::from(*visibility)), Ok(*visibility));
+        }
+    }
+
+    #[test]
+    fn unknown_visibilities_are_rejected() {
+        assert_eq!(Visibility::try_from(3), Err(3));
+        assert_eq!(Visibility::try_from(-1), Err(-1));
+    }
+
+    #[test]
+    fn unknown_stored_visibilities_fall_back_to_local() {
+        assert_eq!(Visibility::stored(7), Visibility::Local);
+        assert!(!Visibility::stored(7).can_share());
+    }
+}
@@ -0,0 +1,100 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use super::{DefaultConnection, schema::login_events};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+pub const HISTORY_LENGTH: i64 = 100;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct LoginEvent {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub success: bool,
+    pub failure: Option<String>,
+    pub ip: Option<String>,
+    pub network: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="login_events"]
+pub struct NewLoginEvent {
+    pub user_id: i64,
+    pub success: bool,
+    pub failure: Option<String>,
+    pub ip: Option<String>,
+    pub network: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl LoginEvent {
+
+    pub fn of_user (user: UserId, db: &DefaultConnection) -> Result<Vec<LoginEvent>, diesel::result::Error> {
+        login_events::table
+            .filter(login_events::user_id.eq(*user))
+            .order(login_events::created_at.desc())
+            .limit(HISTORY_LENGTH)
+            .load::<LoginEvent>(db)
+    }
+
+    pub fn latest (user_id: i64, db: &DefaultConnection) -> Result<Option<LoginEvent>, diesel::result::Error> {
+        login_events::table
+            .filter(login_events::user_id.eq(user_id))
+            .order((login_events::created_at.desc(), login_events::id.desc()))
+            .first::<LoginEvent>(db)
+            .optional()
+    }
+
+    // Failures past the history are dropped, successful sign-ins stay since new-device warnings compare against them
+    pub fn prune_failures (user_id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let recent = login_events::table
+            .filter(login_events::user_id.eq(user_id))
+            .order((login_events::created_at.desc(), login_events::id.desc()))
+            .limit(HISTORY_LENGTH)
+            .select(login_events::id);
+
+        diesel::delete(login_events::table
+                .filter(login_events::user_id.eq(user_id)
+                    .and(login_events::success.eq(false))
+                    .and(login_events::id.ne_all(recent))))
+            .execute(db)
+    }
+
+    pub fn has_succeeded_before (user_id: i64, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(login_events::table
+            .filter(login_events::user_id.eq(user_id)
+                .and(login_events::success.eq(true)))
+            .count()
+            .get_result::<i64>(db)? > 0)
+    }
+
+    pub fn user_agent_seen (user_id: i64, user_agent: &Option<String>, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        let seen = login_events::table
+            .filter(login_events::user_id.eq(user_id)
+                .and(login_events::success.eq(true)));
+
+        Ok(match user_agent {
+            Some(user_agent) => seen.filter(login_events::user_agent.eq(user_agent)).count().get_result::<i64>(db)?,
+            None => seen.filter(login_events::user_agent.is_null()).count().get_result::<i64>(db)?
+        } > 0)
+    }
+
+    pub fn network_seen (user_id: i64, network: &Option<String>, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        let seen = login_events::table
+            .filter(login_events::user_id.eq(user_id)
+                .and(login_events::success.eq(true)));
+
+        Ok(match network {
+            Some(network) => seen.filter(login_events::network.eq(network)).count().get_result::<i64>(db)?,
+            None => seen.filter(login_events::network.is_null()).count().get_result::<i64>(db)?
+        } > 0)
+    }
+
+}
+
+impl_register_for!(NewLoginEvent, LoginEvent, login_events::table);
@@ -0,0 +1,109 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, pg::upsert::excluded};
+
+use super::{DefaultConnection, schema::{recovery_codes, user_mfa}};
+
+#[derive(Queryable, Clone, Debug)]
+pub struct UserMfa {
+    pub user_id: i64,
+    pub secret: String,
+    pub confirmed: bool,
+    pub last_used_step: i64,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="user_mfa"]
+pub struct NewUserMfa {
+    pub user_id: i64,
+    pub secret: String,
+}
+
+impl UserMfa {
+
+    pub fn of_user (user_id: i64, db: &DefaultConnection) -> Result<Option<UserMfa>, diesel::result::Error> {
+        user_mfa::table
+            .find(user_id)
+            .first::<UserMfa>(db)
+            .optional()
+    }
+
+    pub fn is_enabled (user_id: i64, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(UserMfa::of_user(user_id, db)?
+            .map(|mfa| mfa.confirmed)
+            .unwrap_or(false))
+    }
+
+    // Starting over replaces a pending enrollment, a confirmed one has to be disabled first
+    pub fn enroll (new: NewUserMfa, db: &DefaultConnection) -> Result<UserMfa, diesel::result::Error> {
+        diesel::insert_into(user_mfa::table)
+            .values(new)
+            .on_conflict(user_mfa::user_id)
+            .do_update()
+            .set((
+                user_mfa::secret.eq(excluded(user_mfa::secret)),
+                user_mfa::confirmed.eq(false),
+                user_mfa::last_used_step.eq(0),
+            ))
+            .get_result::<UserMfa>(db)
+    }
+
+    pub fn confirm (user_id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(user_mfa::table.find(user_id))
+            .set(user_mfa::confirmed.eq(true))
+            .execute(db)
+    }
+
+    // Only moves forward, so two requests racing with the same code can't both win
+    pub fn use_step (user_id: i64, step: i64, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(diesel::update(user_mfa::table
+                .filter(user_mfa::user_id.eq(user_id)
+                    .and(user_mfa::last_used_step.lt(step))))
+            .set(user_mfa::last_used_step.eq(step))
+            .execute(db)? == 1)
+    }
+
+    pub fn disable (user_id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::delete(recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id)))
+            .execute(db)?;
+
+        diesel::delete(user_mfa::table.find(user_id))
+            .execute(db)
+    }
+
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="recovery_codes"]
+pub struct NewRecoveryCode {
+    pub code_hash: String,
+    pub user_id: i64,
+}
+
+pub struct RecoveryCode;
+
+impl RecoveryCode {
+
+    pub fn replace_for (user_id: i64, hashes: Vec<String>, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::delete(recovery_codes::table
+                .filter(recovery_codes::user_id.eq(user_id)))
+            .execute(db)?;
+
+        diesel::insert_into(recovery_codes::table)
+            .values(hashes.into_iter()
+                .map(|code_hash| NewRecoveryCode { code_hash, user_id })
+                .collect::<Vec<NewRecoveryCode>>())
+            .execute(db)
+    }
+
+    pub fn consume (user_id: i64, hash: &str, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(diesel::update(recovery_codes::table
+                .filter(recovery_codes::code_hash.eq(hash)
+                    .and(recovery_codes::user_id.eq(user_id))
+                    .and(recovery_codes::used.eq(false))))
+            .set(recovery_codes::used.eq(true))
+            .execute(db)? == 1)
+    }
+
+}
@@ -1,34 +1,67 @@
 use diesel::{Connection, Expression, Table, pg::PgConnection, result::Error};
-use rocket::http::Status;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use rocket::{Request, State, config::Config, http::Status, request::{FromRequest, Outcome}};
 use std::env;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use crate::routing::ToStatus;
 use diesel::query_builder::{AsChangeset};
 pub mod schema;
 pub mod user;
-// pub mod persona;
+pub mod persona;
 pub mod contact;
+pub mod token;
+pub mod session;
+pub mod outbox;
+pub mod mfa;
+pub mod access_token;
+pub mod role;
+pub mod audit;
+pub mod login_event;
+pub mod share_link;
+
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+pub const CHECKOUT_TIMEOUT_SECS: u64 = 5;
+
+type Manager = ConnectionManager<PgConnection>;
+
+pub(crate) fn database_url () -> String {
+    env::var("DATABASE_URL")
+        .expect("DB_URL must be set")
+}
 
-fn establish_connection() -> PgConnection {
+#[derive(Clone)]
+pub struct DBPool (Pool<Manager>);
 
-    let database_url = env::var("DATABASE_URL")
-        .expect("DB_URL must be set");
-    let connection = PgConnection::establish(&database_url[..])
-        .expect(&format!("Error connecting to {}", database_url));
+impl DBPool {
 
-    connection
-}
-pub struct DBState (PgConnection);
+    pub fn new (config: &Config) -> DBPool {
+        let size = config.get_int("database_pool_size")
+            .map(|size| size as u32)
+            .unwrap_or(DEFAULT_POOL_SIZE);
+
+        let database_url = database_url();
+        let pool = Pool::builder()
+            .max_size(size)
+            .test_on_check_out(true)
+            .connection_timeout(Duration::from_secs(CHECKOUT_TIMEOUT_SECS))
+            .build(Manager::new(&database_url[..]))
+            .expect(&format!("Error connecting to {}", database_url));
 
-impl DBState {
+        DBPool(pool)
+    }
 
-    pub fn new () -> DBState {
-        DBState(establish_connection())
+    pub fn get (&self) -> Result<DBConnection, diesel::r2d2::PoolError> {
+        self.0.get().map(DBConnection)
     }
 
 }
 
-impl std::ops::Deref for DBState {
+pub struct DBConnection (PooledConnection<Manager>);
+
+impl std::ops::Deref for DBConnection {
 
     type Target = PgConnection;
 
@@ -38,7 +71,7 @@ impl std::ops::Deref for DBState {
 
 }
 
-impl std::ops::DerefMut for DBState {
+impl std::ops::DerefMut for DBConnection {
 
     fn deref_mut (&mut self) -> &mut Self::Target {
         &mut self.0
@@ -46,12 +79,65 @@ impl std::ops::DerefMut for DBState {
 
 }
 
-unsafe impl Send for DBState {
+// Guards that need the database borrow the request's one connection and leave it here for the handler,
+// so a request never holds more than one of the pool's connections
+#[derive(Default)]
+struct RequestConnection {
+    parked: Mutex<Option<DBConnection>>,
+    handed_out: AtomicBool,
+}
+
+impl DBConnection {
+
+    fn check_out (request: &Request) -> Outcome<DBConnection, ()> {
+        let pool = request.guard::<State<DBPool>>()?;
+
+        match pool.get() {
+            Ok(conn) => Outcome::Success(conn),
+            Err(e) => {
+                println!("\t=>\u{001b}[1;31m {:?}\u{001b}[0m", e);
+                Outcome::Failure((Status::ServiceUnavailable, ()))
+            }
+        }
+    }
+
+    // For request guards, which go before the handler's own `DBConnection` in its arguments
+    pub fn with_request<T, F> (request: &Request, f: F) -> Outcome<T, ()>
+        where F: FnOnce(&DefaultConnection) -> Outcome<T, ()> {
+        let slot = request.local_cache(RequestConnection::default);
+
+        // The handler already took it, because it lists its connection before this guard
+        if slot.handed_out.load(Ordering::SeqCst) {
+            println!("\t=>\u{001b}[1;33m {} takes its connection before a guard that needs one\u{001b}[0m", request.uri());
+            return f(&DBConnection::check_out(request)?)
+        }
+
+        let parked = slot.parked.lock().unwrap().take();
+        let conn = match parked {
+            Some(conn) => conn,
+            None => DBConnection::check_out(request)?
+        };
+
+        let outcome = f(&conn);
+        *slot.parked.lock().unwrap() = Some(conn);
+        outcome
+    }
 
 }
 
-unsafe impl Sync for DBState {
-    
+impl<'a, 'r> FromRequest<'a, 'r> for DBConnection {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let slot = request.local_cache(RequestConnection::default);
+        slot.handed_out.store(true, Ordering::SeqCst);
+
+        let parked = slot.parked.lock().unwrap().take();
+        match parked {
+            Some(conn) => Outcome::Success(conn),
+            None => DBConnection::check_out(request)
+        }
+    }
 }
 
 pub type DefaultConnection = PgConnection;
@@ -0,0 +1,82 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, Connection, ExpressionMethods, NullableExpressionMethods, QueryDsl, RunQueryDsl, dsl::{IntervalDsl, now}};
+
+use super::{DefaultConnection, schema::outbox};
+use crate::impl_register_for;
+
+pub const MAX_ATTEMPTS: i32 = 5;
+// A worker that dies mid-batch leaves its mails to somebody else after this long
+pub const CLAIM_MINUTES: i32 = 5;
+
+#[derive(Queryable, Clone, Debug)]
+pub struct Mail {
+    pub id: i64,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub created_at: NaiveDateTime,
+    pub sent_at: Option<NaiveDateTime>,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub claimed_until: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="outbox"]
+pub struct NewMail {
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl NewMail {
+    pub fn new(recipient: String, subject: String, body: String) -> Self { Self { recipient, subject, body } }
+}
+
+impl Mail {
+
+    // Claimed mails are committed before anything is sent, so neither a failed update
+    // nor another instance can have them delivered twice
+    pub fn claim_pending (limit: i64, db: &DefaultConnection) -> Result<Vec<Mail>, diesel::result::Error> {
+        db.transaction(|| {
+            let ids = outbox::table
+                .filter(outbox::sent_at.is_null())
+                .filter(outbox::attempts.lt(MAX_ATTEMPTS))
+                .filter(outbox::claimed_until.is_null()
+                    .or(outbox::claimed_until.lt(now.nullable())))
+                .order(outbox::id.asc())
+                .limit(limit)
+                .select(outbox::id)
+                .for_update()
+                .skip_locked()
+                .load::<i64>(db)?;
+
+            diesel::update(outbox::table.filter(outbox::id.eq_any(ids)))
+                .set(outbox::claimed_until.eq((now + CLAIM_MINUTES.minutes()).nullable()))
+                .get_results::<Mail>(db)
+        })
+    }
+
+    pub fn mark_sent (&self, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(outbox::table.find(self.id))
+            .set((
+                outbox::sent_at.eq(now),
+                outbox::attempts.eq(self.attempts + 1),
+                outbox::claimed_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(db)
+    }
+
+    pub fn mark_failed (&self, error: String, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(outbox::table.find(self.id))
+            .set((
+                outbox::attempts.eq(self.attempts + 1),
+                outbox::last_error.eq(Some(error)),
+                outbox::claimed_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(db)
+    }
+
+}
+
+impl_register_for!(NewMail, Mail, outbox::table);
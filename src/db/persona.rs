@@ -0,0 +1,231 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+use super::{DefaultConnection, schema::{contacts, persona_copies, persona_info, persona_tokens, personas}};
+use crate::db::contact::{Contact, info::BareInfo};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct Persona {
+    pub id: i64,
+    pub private: bool,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub label: String,
+    pub name: String,
+    pub icon: Option<Vec<u8>>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="personas"]
+pub struct NewPersona {
+    pub private: bool,
+    pub user_id: i64,
+    pub label: String,
+    pub name: String,
+    pub icon: Option<Vec<u8>>,
+}
+
+#[derive(AsChangeset, Deserialize, Default, Clone, Debug)]
+#[table_name="personas"]
+pub struct UpdatePersona {
+    pub private: Option<bool>,
+    pub label: Option<String>,
+    pub name: Option<String>,
+    pub icon: Option<Option<Vec<u8>>>,
+}
+
+impl UpdatePersona {
+
+    pub fn is_empty(&self) -> bool {
+        self.private.is_none() && self.label.is_none() && self.name.is_none() && self.icon.is_none()
+    }
+
+}
+
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name="persona_info"]
+pub struct PersonaFragment {
+    pub key: String,
+    pub value: String,
+    pub persona_id: i64,
+}
+
+// Tokens are signed like share links, this row is what lets the owner list and revoke them
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct PersonaToken {
+    pub id: String,
+    pub persona_id: i64,
+    pub recipient_id: Option<i64>,
+    pub created_at: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    #[serde(skip)]
+    pub revoked: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="persona_tokens"]
+pub struct NewPersonaToken {
+    pub id: String,
+    pub persona_id: i64,
+    pub recipient_id: Option<i64>,
+    pub expires: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name="persona_copies"]
+pub struct PersonaCopy {
+    pub user_id: i64,
+    pub persona_id: i64,
+    pub contact_id: i64,
+}
+
+impl Persona {
+
+    pub fn of_user (user: UserId, db: &DefaultConnection) -> Result<Vec<Persona>, diesel::result::Error> {
+        personas::table
+            .filter(personas::user_id.eq(*user))
+            .order(personas::created_at.asc())
+            .load::<Persona>(db)
+    }
+
+    // Somebody else's persona looks the same as a missing one
+    pub fn query_for (user: UserId, id: i64, db: &DefaultConnection) -> Result<Persona, diesel::result::Error> {
+        personas::table
+            .filter(personas::id.eq(id)
+                .and(personas::user_id.eq(*user)))
+            .first::<Persona>(db)
+    }
+
+    pub fn force_get_by_id (id: i64, db: &DefaultConnection) -> Result<Persona, diesel::result::Error> {
+        personas::table
+            .find(id)
+            .first::<Persona>(db)
+    }
+
+    pub fn update (user: UserId, id: i64, changes: &UpdatePersona, db: &DefaultConnection) -> Result<Persona, diesel::result::Error> {
+        if changes.is_empty() {
+            return Persona::query_for(user, id, db)
+        }
+
+        diesel::update(personas::table
+                .filter(personas::id.eq(id)
+                    .and(personas::user_id.eq(*user))))
+            .set(changes)
+            .get_result::<Persona>(db)
+    }
+
+    pub fn delete (user: UserId, id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let deleted = diesel::delete(personas::table
+                .filter(personas::id.eq(id)
+                    .and(personas::user_id.eq(*user))))
+            .execute(db)?;
+
+        if deleted == 0 {
+            return Err(diesel::result::Error::NotFound)
+        }
+
+        Ok(deleted)
+    }
+
+    pub fn info (&self, db: &DefaultConnection) -> Result<BareInfo, diesel::result::Error> {
+        let fragments = persona_info::table
+            .filter(persona_info::persona_id.eq(self.id))
+            .order((persona_info::key.asc(), persona_info::value.asc()))
+            .load::<PersonaFragment>(db)?;
+
+        let mut info = BareInfo::new();
+        for fragment in fragments {
+            info.entry(fragment.key)
+                .or_insert_with(Vec::new)
+                .push(fragment.value);
+        }
+
+        Ok(info)
+    }
+
+    // A persona's fields are always sent as a whole
+    pub fn replace_info (&self, info: &BareInfo, db: &DefaultConnection) -> Result<BareInfo, diesel::result::Error> {
+        diesel::delete(persona_info::table
+                .filter(persona_info::persona_id.eq(self.id)))
+            .execute(db)?;
+
+        let fragments = info.iter()
+            .flat_map(|(key, values)| values.iter()
+                .map(move |value| (key.clone(), value.clone())))
+            .collect::<std::collections::HashSet<(String, String)>>()
+            .into_iter()
+            .map(|(key, value)| PersonaFragment {
+                key,
+                value,
+                persona_id: self.id
+            })
+            .collect::<Vec<PersonaFragment>>();
+
+        diesel::insert_into(persona_info::table)
+            .values(&fragments)
+            .execute(db)?;
+
+        self.info(db)
+    }
+
+}
+
+impl PersonaToken {
+
+    pub fn query_by_id (id: &str, db: &DefaultConnection) -> Result<PersonaToken, diesel::result::Error> {
+        persona_tokens::table
+            .find(id)
+            .first::<PersonaToken>(db)
+    }
+
+    pub fn is_usable (&self) -> bool {
+        !self.revoked && self.expires > chrono::Utc::now().naive_utc()
+    }
+
+    pub fn outstanding (user: UserId, persona: i64, db: &DefaultConnection) -> Result<Vec<PersonaToken>, diesel::result::Error> {
+        persona_tokens::table
+            .inner_join(personas::table)
+            .filter(personas::user_id.eq(*user)
+                .and(persona_tokens::persona_id.eq(persona))
+                .and(persona_tokens::revoked.eq(false))
+                .and(persona_tokens::expires.gt(diesel::dsl::now)))
+            .order(persona_tokens::created_at.desc())
+            .select(persona_tokens::all_columns)
+            .load::<PersonaToken>(db)
+    }
+
+    // Only the owner of the persona can revoke its tokens
+    pub fn revoke (user: UserId, id: &str, db: &DefaultConnection) -> Result<PersonaToken, diesel::result::Error> {
+        diesel::update(persona_tokens::table
+                .filter(persona_tokens::id.eq(id)
+                    .and(persona_tokens::revoked.eq(false))
+                    .and(persona_tokens::persona_id.eq_any(personas::table
+                        .filter(personas::user_id.eq(*user))
+                        .select(personas::id)))))
+            .set(persona_tokens::revoked.eq(true))
+            .get_result::<PersonaToken>(db)
+    }
+
+}
+
+impl PersonaCopy {
+
+    pub fn contact_of (user: UserId, persona: i64, db: &DefaultConnection) -> Result<Option<Contact>, diesel::result::Error> {
+        persona_copies::table
+            .inner_join(contacts::table)
+            .filter(persona_copies::user_id.eq(*user)
+                .and(persona_copies::persona_id.eq(persona)))
+            .select(contacts::all_columns)
+            .first::<Contact>(db)
+            .optional()
+    }
+
+}
+
+impl_register_for!(NewPersona, Persona, personas::table);
+impl_register_for!(NewPersonaToken, PersonaToken, persona_tokens::table);
+impl_register_for!(PersonaCopy, PersonaCopy, persona_copies::table);
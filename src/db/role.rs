@@ -0,0 +1,58 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use super::{DefaultConnection, schema::{role_permissions, roles, users}};
+use crate::db::user::UserId;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Role {
+    pub name: String,
+    pub permissions: Vec<String>,
+}
+
+impl Role {
+
+    pub fn all (db: &DefaultConnection) -> Result<Vec<Role>, diesel::result::Error> {
+        let names = roles::table
+            .order(roles::name.asc())
+            .load::<String>(db)?;
+
+        let grants = role_permissions::table
+            .order(role_permissions::permission.asc())
+            .load::<(String, String)>(db)?;
+
+        Ok(names.into_iter()
+            .map(|name| Role {
+                permissions: grants.iter()
+                    .filter(|(role, _)| *role == name)
+                    .map(|(_, permission)| permission.clone())
+                    .collect(),
+                name
+            })
+            .collect())
+    }
+
+    pub fn exists (name: &str, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(roles::table
+            .find(name)
+            .count()
+            .get_result::<i64>(db)? > 0)
+    }
+
+    pub fn assign (user: i64, role: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(user))
+            .set(users::role.eq(role))
+            .execute(db)
+    }
+
+}
+
+pub fn has_permission (user: UserId, permission: &str, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+    Ok(role_permissions::table
+        .filter(role_permissions::permission.eq(permission)
+            .and(role_permissions::role.eq_any(users::table
+                .filter(users::id.eq(*user))
+                .select(users::role))))
+        .count()
+        .get_result::<i64>(db)? > 0)
+}
@@ -1,3 +1,31 @@
+table! {
+    access_tokens (id) {
+        id -> Int8,
+        user_id -> Int8,
+        name -> Varchar,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        created_at -> Timestamp,
+        expires -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int8,
+        actor_id -> Nullable<Int8>,
+        action -> Varchar,
+        target_user_id -> Nullable<Int8>,
+        details -> Nullable<Text>,
+        created_at -> Timestamp,
+        target_contact_id -> Nullable<Int8>,
+        before_state -> Nullable<Text>,
+        after_state -> Nullable<Text>,
+    }
+}
+
 table! {
     contacts (id) {
         id -> Int8,
@@ -8,6 +36,24 @@ table! {
     }
 }
 
+table! {
+    directory_hidden_keys (contact_id, key) {
+        contact_id -> Int8,
+        key -> Varchar,
+    }
+}
+
+table! {
+    email_verifications (token_hash) {
+        token_hash -> Varchar,
+        user_id -> Int8,
+        email -> Varchar,
+        created_at -> Timestamp,
+        expires -> Timestamp,
+        used -> Bool,
+    }
+}
+
 table! {
     info (key, value, contact_id) {
         key -> Varchar,
@@ -15,14 +61,159 @@ table! {
         contact_id -> Int8,
     }
 }
-//
-// table! {
-//     personas (id) {
-//         id -> Int8,
-//         private -> Bool,
-//         user_id -> Int8,
-//     }
-// }
+
+table! {
+    login_events (id) {
+        id -> Int8,
+        user_id -> Int8,
+        success -> Bool,
+        failure -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        network -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    outbox (id) {
+        id -> Int8,
+        recipient -> Varchar,
+        subject -> Varchar,
+        body -> Text,
+        created_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+        attempts -> Int4,
+        last_error -> Nullable<Text>,
+        claimed_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    password_resets (token_hash) {
+        token_hash -> Varchar,
+        user_id -> Int8,
+        created_at -> Timestamp,
+        expires -> Timestamp,
+        used -> Bool,
+    }
+}
+
+table! {
+    persona_copies (user_id, persona_id) {
+        user_id -> Int8,
+        persona_id -> Int8,
+        contact_id -> Int8,
+    }
+}
+
+table! {
+    persona_info (key, value, persona_id) {
+        key -> Varchar,
+        value -> Varchar,
+        persona_id -> Int8,
+    }
+}
+
+table! {
+    persona_tokens (id) {
+        id -> Varchar,
+        persona_id -> Int8,
+        recipient_id -> Nullable<Int8>,
+        created_at -> Timestamp,
+        expires -> Timestamp,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    personas (id) {
+        id -> Int8,
+        private -> Bool,
+        user_id -> Int8,
+        label -> Varchar,
+        name -> Varchar,
+        icon -> Nullable<Bytea>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    recovery_codes (code_hash) {
+        code_hash -> Varchar,
+        user_id -> Int8,
+        used -> Bool,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int8,
+        token_hash -> Varchar,
+        family -> Varchar,
+        user_id -> Int8,
+        created_at -> Timestamp,
+        expires -> Timestamp,
+        rotated -> Bool,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Varchar,
+        expires -> Timestamp,
+    }
+}
+
+table! {
+    role_permissions (role, permission) {
+        role -> Varchar,
+        permission -> Varchar,
+    }
+}
+
+table! {
+    roles (name) {
+        name -> Varchar,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Varchar,
+        user_id -> Int8,
+        user_agent -> Nullable<Varchar>,
+        ip -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_used_at -> Timestamp,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    share_links (id) {
+        id -> Varchar,
+        contact_id -> Int8,
+        creator -> Int8,
+        recipient_id -> Nullable<Int8>,
+        max_uses -> Nullable<Int4>,
+        uses -> Int4,
+        created_at -> Timestamp,
+        expires -> Timestamp,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    user_mfa (user_id) {
+        user_id -> Int8,
+        secret -> Varchar,
+        confirmed -> Bool,
+        last_used_step -> Int8,
+        created_at -> Timestamp,
+    }
+}
 
 table! {
     users (id) {
@@ -30,7 +221,11 @@ table! {
         username -> Varchar,
         email -> Varchar,
         password -> Varchar,
-        level -> Int4,
+        email_verified -> Bool,
+        role -> Varchar,
+        suspended_at -> Nullable<Timestamp>,
+        failed_logins -> Int4,
+        locked_until -> Nullable<Timestamp>,
     }
 }
 
@@ -38,16 +233,53 @@ table! {
     users_contacts_join (user_id, contact_id) {
         user_id -> Int8,
         contact_id -> Int8,
+        permission -> Int2,
     }
 }
 
+joinable!(access_tokens -> users (user_id));
+joinable!(directory_hidden_keys -> contacts (contact_id));
+joinable!(email_verifications -> users (user_id));
 joinable!(info -> contacts (contact_id));
+joinable!(login_events -> users (user_id));
+joinable!(password_resets -> users (user_id));
+joinable!(persona_copies -> contacts (contact_id));
+joinable!(persona_copies -> personas (persona_id));
+joinable!(persona_info -> personas (persona_id));
+joinable!(persona_tokens -> personas (persona_id));
+joinable!(personas -> users (user_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(refresh_tokens -> users (user_id));
+joinable!(role_permissions -> roles (role));
+joinable!(sessions -> users (user_id));
+joinable!(share_links -> contacts (contact_id));
+joinable!(user_mfa -> users (user_id));
+joinable!(users -> roles (role));
 joinable!(users_contacts_join -> contacts (contact_id));
 joinable!(users_contacts_join -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    access_tokens,
+    audit_log,
     contacts,
+    directory_hidden_keys,
+    email_verifications,
     info,
+    login_events,
+    outbox,
+    password_resets,
+    persona_copies,
+    persona_info,
+    persona_tokens,
+    personas,
+    recovery_codes,
+    refresh_tokens,
+    revoked_tokens,
+    role_permissions,
+    roles,
+    sessions,
+    share_links,
+    user_mfa,
     users,
     users_contacts_join,
 );
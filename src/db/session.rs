@@ -0,0 +1,124 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, dsl::{IntervalDsl, now}};
+use serde::Serialize;
+
+use super::{DefaultConnection, schema::sessions, token::RefreshToken};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: NaiveDateTime,
+    #[serde(skip)]
+    pub revoked: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="sessions"]
+pub struct NewSession {
+    pub id: String,
+    pub user_id: i64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl Session {
+
+    pub fn is_active (id: &str, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        match sessions::table.find(id).first::<Session>(db) {
+            Ok(session) => Ok(!session.revoked),
+            Err(diesel::result::Error::NotFound) => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+
+    // Writes at most once a minute per session, so authenticated requests don't all turn into updates
+    pub fn touch (id: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(sessions::table
+                .filter(sessions::id.eq(id)
+                    .and(sessions::last_used_at.lt(now - 1.minute()))))
+            .set(sessions::last_used_at.eq(now))
+            .execute(db)
+    }
+
+    pub fn renew (id: &str, user_agent: Option<String>, ip: Option<String>, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(sessions::table.find(id))
+            .set((
+                sessions::last_used_at.eq(now),
+                sessions::user_agent.eq(user_agent),
+                sessions::ip.eq(ip),
+            ))
+            .execute(db)
+    }
+
+    pub fn of_user (user: UserId, db: &DefaultConnection) -> Result<Vec<Session>, diesel::result::Error> {
+        sessions::table
+            .filter(sessions::user_id.eq(*user)
+                .and(sessions::revoked.eq(false)))
+            .order(sessions::last_used_at.desc())
+            .load::<Session>(db)
+    }
+
+    pub fn revoke (id: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(sessions::table.find(id))
+            .set(sessions::revoked.eq(true))
+            .execute(db)?;
+        RefreshToken::revoke_family(id, db)
+    }
+
+    pub fn end (user: UserId, id: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let ended = diesel::update(sessions::table
+                .filter(sessions::id.eq(id)
+                    .and(sessions::user_id.eq(*user))
+                    .and(sessions::revoked.eq(false))))
+            .set(sessions::revoked.eq(true))
+            .execute(db)?;
+
+        if ended == 0 {
+            return Err(diesel::result::Error::NotFound)
+        }
+
+        RefreshToken::revoke_family(id, db)?;
+        Ok(ended)
+    }
+
+    pub fn end_all (user: UserId, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let ended = diesel::update(sessions::table
+                .filter(sessions::user_id.eq(*user)
+                    .and(sessions::revoked.eq(false))))
+            .set(sessions::revoked.eq(true))
+            .returning(sessions::id)
+            .get_results::<String>(db)?;
+
+        for id in &ended {
+            RefreshToken::revoke_family(id, db)?;
+        }
+
+        Ok(ended.len())
+    }
+
+    pub fn end_all_except (user: UserId, keep: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        let ended = diesel::update(sessions::table
+                .filter(sessions::user_id.eq(*user)
+                    .and(sessions::id.ne(keep))
+                    .and(sessions::revoked.eq(false))))
+            .set(sessions::revoked.eq(true))
+            .returning(sessions::id)
+            .get_results::<String>(db)?;
+
+        for id in &ended {
+            RefreshToken::revoke_family(id, db)?;
+        }
+
+        Ok(ended.len())
+    }
+
+}
+
+impl_register_for!(NewSession, Session, sessions::table);
@@ -0,0 +1,99 @@
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::Serialize;
+
+use super::{DefaultConnection, schema::share_links};
+use crate::db::user::UserId;
+use crate::impl_register_for;
+
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct ShareLink {
+    pub id: String,
+    pub contact_id: i64,
+    #[serde(skip)]
+    pub creator: i64,
+    pub recipient_id: Option<i64>,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub created_at: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    #[serde(skip)]
+    pub revoked: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="share_links"]
+pub struct NewShareLink {
+    pub id: String,
+    pub contact_id: i64,
+    pub creator: i64,
+    pub recipient_id: Option<i64>,
+    pub max_uses: Option<i32>,
+    pub expires: NaiveDateTime,
+}
+
+impl ShareLink {
+
+    pub fn query_by_id (id: &str, db: &DefaultConnection) -> Result<ShareLink, diesel::result::Error> {
+        share_links::table
+            .find(id)
+            .first::<ShareLink>(db)
+    }
+
+    // Holds the row until the transaction ends, so concurrent accepts can't exceed the use limit
+    pub fn lock (id: &str, db: &DefaultConnection) -> Result<ShareLink, diesel::result::Error> {
+        share_links::table
+            .find(id)
+            .for_update()
+            .first::<ShareLink>(db)
+    }
+
+    pub fn is_usable (&self) -> bool {
+        !self.revoked
+            && self.expires > chrono::Utc::now().naive_utc()
+            && self.max_uses.map(|max| self.uses < max).unwrap_or(true)
+    }
+
+    pub fn outstanding (user: UserId, contact: Option<i64>, db: &DefaultConnection) -> Result<Vec<ShareLink>, diesel::result::Error> {
+        let mut query = share_links::table
+            .filter(share_links::creator.eq(*user)
+                .and(share_links::revoked.eq(false))
+                .and(share_links::expires.gt(diesel::dsl::now)))
+            .into_boxed();
+
+        if let Some(contact) = contact {
+            query = query.filter(share_links::contact_id.eq(contact));
+        }
+
+        Ok(query
+            .order(share_links::created_at.desc())
+            .load::<ShareLink>(db)?
+            .into_iter()
+            .filter(ShareLink::is_usable)
+            .collect())
+    }
+
+    pub fn count_use (id: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(share_links::table.find(id))
+            .set(share_links::uses.eq(share_links::uses + 1))
+            .execute(db)
+    }
+
+    pub fn mark_revoked (id: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(share_links::table.find(id))
+            .set(share_links::revoked.eq(true))
+            .execute(db)
+    }
+
+    pub fn revoke (user: UserId, id: &str, db: &DefaultConnection) -> Result<ShareLink, diesel::result::Error> {
+        diesel::update(share_links::table
+                .filter(share_links::id.eq(id)
+                    .and(share_links::creator.eq(*user))
+                    .and(share_links::revoked.eq(false))))
+            .set(share_links::revoked.eq(true))
+            .get_result::<ShareLink>(db)
+    }
+
+}
+
+impl_register_for!(NewShareLink, ShareLink, share_links::table);
@@ -0,0 +1,177 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl, dsl::now, result::{DatabaseErrorKind::UniqueViolation, Error::DatabaseError}};
+
+use super::{DefaultConnection, Register, schema::{email_verifications, password_resets, refresh_tokens, revoked_tokens}};
+use crate::impl_register_for;
+
+#[derive(Queryable, Insertable, Clone, Debug)]
+#[table_name="revoked_tokens"]
+pub struct RevokedToken {
+    pub jti: String,
+    pub expires: NaiveDateTime,
+}
+
+impl RevokedToken {
+
+    pub fn new (jti: String, expires: NaiveDateTime) -> Self {
+        Self { jti, expires }
+    }
+
+    pub fn revoke (self, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        match self.register(db) {
+            Ok(_) | Err(DatabaseError(UniqueViolation, _)) => Ok(()),
+            Err(e) => Err(e)
+        }
+    }
+
+    pub fn active (db: &DefaultConnection) -> Result<Vec<RevokedToken>, diesel::result::Error> {
+        revoked_tokens::table
+            .filter(revoked_tokens::expires.gt(now))
+            .load::<RevokedToken>(db)
+    }
+
+    pub fn collect_garbage (db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::delete(revoked_tokens::table)
+            .filter(revoked_tokens::expires.le(now))
+            .execute(db)
+    }
+
+}
+
+impl_register_for!(RevokedToken, RevokedToken, revoked_tokens::table);
+
+#[derive(Queryable, Clone, Debug)]
+pub struct RefreshToken {
+    pub id: i64,
+    pub token_hash: String,
+    pub family: String,
+    pub user_id: i64,
+    pub created_at: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    pub rotated: bool,
+    pub revoked: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="refresh_tokens"]
+pub struct NewRefreshToken {
+    pub token_hash: String,
+    pub family: String,
+    pub user_id: i64,
+    pub expires: NaiveDateTime,
+}
+
+impl RefreshToken {
+
+    pub fn query_by_hash (hash: &str, db: &DefaultConnection) -> Result<RefreshToken, diesel::result::Error> {
+        refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash))
+            .first::<RefreshToken>(db)
+    }
+
+    pub fn is_valid (&self) -> bool {
+        !self.revoked && self.expires > Utc::now().naive_utc()
+    }
+
+    // Only one caller can win the exchange, everybody else gets NotFound
+    pub fn mark_rotated (&self, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        let updated = diesel::update(refresh_tokens::table
+                .filter(refresh_tokens::id.eq(self.id)
+                    .and(refresh_tokens::rotated.eq(false))))
+            .set(refresh_tokens::rotated.eq(true))
+            .execute(db)?;
+
+        match updated {
+            0 => Err(diesel::result::Error::NotFound),
+            _ => Ok(())
+        }
+    }
+
+    pub fn revoke_family (family: &str, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(refresh_tokens::table
+                .filter(refresh_tokens::family.eq(family)))
+            .set(refresh_tokens::revoked.eq(true))
+            .execute(db)
+    }
+
+}
+
+impl_register_for!(NewRefreshToken, RefreshToken, refresh_tokens::table);
+
+#[derive(Queryable, Clone, Debug)]
+pub struct PasswordReset {
+    pub token_hash: String,
+    pub user_id: i64,
+    pub created_at: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    pub used: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="password_resets"]
+pub struct NewPasswordReset {
+    pub token_hash: String,
+    pub user_id: i64,
+    pub expires: NaiveDateTime,
+}
+
+impl PasswordReset {
+
+    // Burns the token and hands back its user, a token can only ever be consumed once
+    pub fn consume (hash: &str, db: &DefaultConnection) -> Result<i64, diesel::result::Error> {
+        diesel::update(password_resets::table
+                .filter(password_resets::token_hash.eq(hash)
+                    .and(password_resets::used.eq(false))
+                    .and(password_resets::expires.gt(now))))
+            .set(password_resets::used.eq(true))
+            .returning(password_resets::user_id)
+            .get_result::<i64>(db)
+    }
+
+    pub fn invalidate_for (user_id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(password_resets::table
+                .filter(password_resets::user_id.eq(user_id)
+                    .and(password_resets::used.eq(false))))
+            .set(password_resets::used.eq(true))
+            .execute(db)
+    }
+
+}
+
+impl_register_for!(NewPasswordReset, PasswordReset, password_resets::table);
+
+#[derive(Queryable, Clone, Debug)]
+pub struct EmailVerification {
+    pub token_hash: String,
+    pub user_id: i64,
+    pub email: String,
+    pub created_at: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    pub used: bool,
+}
+
+#[derive(Insertable, Clone, Debug)]
+#[table_name="email_verifications"]
+pub struct NewEmailVerification {
+    pub token_hash: String,
+    pub user_id: i64,
+    pub email: String,
+    pub expires: NaiveDateTime,
+}
+
+impl EmailVerification {
+
+    // Burns the token and hands back the user and the address it was sent to
+    pub fn consume (hash: &str, db: &DefaultConnection) -> Result<(i64, String), diesel::result::Error> {
+        diesel::update(email_verifications::table
+                .filter(email_verifications::token_hash.eq(hash)
+                    .and(email_verifications::used.eq(false))
+                    .and(email_verifications::expires.gt(now))))
+            .set(email_verifications::used.eq(true))
+            .returning((email_verifications::user_id, email_verifications::email))
+            .get_result::<(i64, String)>(db)
+    }
+
+}
+
+impl_register_for!(NewEmailVerification, EmailVerification, email_verifications::table);
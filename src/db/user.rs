@@ -1,7 +1,13 @@
-use super::{DefaultConnection, contact::Contact, schema::{users, contacts, users_contacts_join}};
-use diesel::{QueryDsl, RunQueryDsl};
+use super::{DefaultConnection, contact::{Contact, ContactPermission, UserContactRelation}, schema::{users, contacts, users_contacts_join}};
+use chrono::NaiveDateTime;
+use diesel::{BoolExpressionMethods, OptionalExtension, PgTextExpressionMethods, QueryDsl, RunQueryDsl};
 use serde::{Serialize, Deserialize};
-use bcrypt::{BcryptError, DEFAULT_COST, hash, verify};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version, password_hash::{SaltString, rand_core::OsRng}};
+use bcrypt::{BcryptError, verify};
+use lazy_static::lazy_static;
+use rocket::config::Config;
+use std::convert::TryFrom;
+use std::sync::RwLock;
 use sha2::{Digest, Sha512};
 use crate::{diesel::ExpressionMethods, impl_query_by_id, impl_register_for};
 use crate::db::Delete;
@@ -9,24 +15,126 @@ use crate::update;
 use std::marker::PhantomData;
 use diesel::result::Error;
 
+// Backed by the `lower(...)` unique indexes, so lookups ignore case the same way the database does
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
 #[derive(Clone, Queryable, Debug)]
 pub struct User {
     pub id: i64,
     pub username: String,
     pub email: String,
     pub password: String,
-    pub level: i32
+    pub email_verified: bool,
+    pub role: String,
+    pub suspended_at: Option<NaiveDateTime>,
+    pub failed_logins: i32,
+    pub locked_until: Option<NaiveDateTime>
 }
 
 impl User {
 
     pub fn query_by_username(username: &String, db: &DefaultConnection) -> Result<User, diesel::result::Error> {
         users::table
-            .filter (users::username.eq(username))
+            .filter (lower(users::username).eq(lower(username)))
+            .limit(1)
+            .first::<User> (db)
+    }
+
+    pub fn query_by_email(email: &String, db: &DefaultConnection) -> Result<User, diesel::result::Error> {
+        users::table
+            .filter (lower(users::email).eq(lower(email)))
             .limit(1)
             .first::<User> (db)
     }
 
+    // Either one works to log in, an `@` means an email first since new usernames can't have one.
+    // Accounts named before that rule still get found by their username when no email matches.
+    pub fn query_by_login(login: &String, db: &DefaultConnection) -> Result<User, diesel::result::Error> {
+        if !login.contains('@') {
+            return User::query_by_username(login, db)
+        }
+
+        match User::query_by_email(login, db) {
+            Err(diesel::result::Error::NotFound) => User::query_by_username(login, db),
+            found => found
+        }
+    }
+
+    // Keeps usernames from ever being mistaken for somebody's email
+    pub fn valid_username(username: &str) -> bool {
+        !username.trim().is_empty() && !username.contains('@')
+    }
+
+    // Only flips the flag while the account still has the address the link was sent to
+    pub fn mark_email_verified(id: i64, email: &String, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table
+                .filter (users::id.eq(id)
+                    .and(users::email.eq(email))))
+            .set (users::email_verified.eq(true))
+            .execute (db)
+    }
+
+    pub fn is_suspended(id: i64, db: &DefaultConnection) -> Result<bool, diesel::result::Error> {
+        Ok(users::table
+            .find (id)
+            .select (users::suspended_at)
+            .first::<Option<NaiveDateTime>> (db)?
+            .is_some ())
+    }
+
+    pub fn record_failed_login(id: i64, db: &DefaultConnection) -> Result<i32, diesel::result::Error> {
+        diesel::update(users::table.find(id))
+            .set (users::failed_logins.eq(users::failed_logins + 1))
+            .returning (users::failed_logins)
+            .get_result::<i32> (db)
+    }
+
+    pub fn lock(id: i64, until: NaiveDateTime, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(id))
+            .set (users::locked_until.eq(Some(until)))
+            .execute (db)
+    }
+
+    pub fn unlock(id: i64, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(users::table.find(id))
+            .set ((
+                users::failed_logins.eq(0),
+                users::locked_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute (db)
+    }
+
+    // Matches on username or email, `%` and `_` in the query are taken literally
+    pub fn search(query: &str, offset: i64, limit: i64, db: &DefaultConnection) -> Result<Vec<User>, diesel::result::Error> {
+        let pattern = format!("%{}%", query
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_"));
+
+        users::table
+            .filter (users::username.ilike(&pattern)
+                .or(users::email.ilike(&pattern)))
+            .order (users::id.asc())
+            .offset (offset)
+            .limit (limit)
+            .load::<User> (db)
+    }
+
+    // Contacts this user created, and contacts they can see in total
+    pub fn contact_counts(&self, db: &DefaultConnection) -> Result<(i64, i64), diesel::result::Error> {
+        let created = contacts::table
+            .filter (contacts::creator.eq(self.id))
+            .count ()
+            .get_result::<i64> (db)?;
+
+        let related = users_contacts_join::table
+            .filter (users_contacts_join::user_id.eq(self.id))
+            .count ()
+            .get_result::<i64> (db)?;
+
+        Ok((created, related))
+    }
+
 }
 
 impl_query_by_id!(User => users::table);
@@ -36,17 +144,20 @@ impl_query_by_id!(User => users::table);
 pub struct NewUser {
     pub username: String,
     pub email: String,
-    pub password: String,
-    pub level: i32
+    pub password: String
 }
 
-#[derive(Clone, AsChangeset, Serialize, Deserialize, Debug)]
+#[derive(Clone, Default, AsChangeset, Serialize, Deserialize, Debug)]
 #[table_name="users"]
 pub struct UpdateUser {
     pub username: Option<String>,
     pub email: Option<String>,
     pub password: Option<String>,
-    pub level: Option<i32>
+    pub email_verified: Option<bool>,
+    pub role: Option<String>,
+    pub suspended_at: Option<Option<NaiveDateTime>>,
+    pub failed_logins: Option<i32>,
+    pub locked_until: Option<Option<NaiveDateTime>>
 }
 
 update! (UpdateUser => NewUser, i64);
@@ -60,14 +171,45 @@ impl Delete for ForUser<User> {
         if self.0 != id {
             return Err(Error::NotFound)
         }
+        hand_over_contacts(id, db)?;
         diesel::delete(users::table)
             .filter(users::id.eq(id))
             .execute(db)
     }
 }
 
+// `contacts.creator` keeps a user from being deleted, so their contacts go to one of the remaining
+// co-owners (the oldest account), or are deleted along with them when nobody else owns them
+pub fn hand_over_contacts(user: i64, db: &DefaultConnection) -> Result<(), Error> {
+    let created = contacts::table
+        .filter (contacts::creator.eq(user))
+        .select (contacts::id)
+        .load::<i64> (db)?;
+
+    for contact in created {
+        let heir = users_contacts_join::table
+            .filter (users_contacts_join::contact_id.eq(contact)
+                .and(users_contacts_join::user_id.ne(user))
+                .and(users_contacts_join::permission.eq(i16::from(ContactPermission::CoOwner))))
+            .order (users_contacts_join::user_id.asc())
+            .select (users_contacts_join::user_id)
+            .first::<i64> (db)
+            .optional ()?;
+
+        match heir {
+            Some(heir) => diesel::update(contacts::table.find(contact))
+                .set (contacts::creator.eq(heir))
+                .execute (db)?,
+            None => diesel::delete(contacts::table.find(contact))
+                .execute (db)?
+        };
+    }
+
+    Ok(())
+}
+
 impl NewUser {
-    pub fn new(username: String, email: String, password: String) -> Self { Self { username, email, password, level: 0 } }
+    pub fn new(username: String, email: String, password: String) -> Self { Self { username, email, password } }
 }
 
 pub trait Password: Clone {
@@ -88,23 +230,124 @@ pub trait Password: Clone {
         out
     }
 
-    fn salt (&self) -> Result<Self, BcryptError> {
-        match hash (self.password (), DEFAULT_COST) {
-            Ok(pass) => { 
-                let mut out = self.clone ();
-                out.set_password (pass); 
-                Ok(out)
-            },
-            Err(e) => { return Err(e); },
+    fn salt (&self) -> Result<Self, PasswordError> {
+        let salt = SaltString::generate (&mut OsRng);
+        let pass = argon2 ()?
+            .hash_password (self.password ().as_bytes (), &salt)?
+            .to_string ();
+
+        let mut out = self.clone ();
+        out.set_password (pass);
+        Ok(out)
+    }
+
+    // Stored hashes tell their scheme apart by prefix: PHC strings for Argon2, `$2?$` for bcrypt
+    fn password_cmp<T: Password> (&self, other: &T) -> Result<bool, PasswordError> {
+        if !self.password ().starts_with (ARGON2_PREFIX) {
+            return Ok(verify (other.password (), self.password ())?)
+        }
+
+        let hash = PasswordHash::new (self.password ())?;
+        match Argon2::default ().verify_password (other.password ().as_bytes (), &hash) {
+            Ok(()) => Ok(true),
+            Err(argon2::password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(e.into ())
         }
     }
 
-    fn password_cmp<T: Password> (&self, other: &T) -> Result<bool, BcryptError> {
-        verify(other.password (), self.password ())
+    fn needs_rehash (&self) -> bool {
+        if !self.password ().starts_with (ARGON2_PREFIX) {
+            return true
+        }
+
+        let current = hash_params ();
+        match PasswordHash::new (self.password ()).and_then (|hash| Params::try_from (&hash)) {
+            Ok(params) => params.m_cost () != current.memory_kib
+                || params.t_cost () != current.iterations
+                || params.p_cost () != current.parallelism,
+            Err(_) => true
+        }
     }
 
 }
 
+pub const ARGON2_PREFIX: &str = "$argon2id$";
+
+#[derive(Clone, Copy, Debug)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1
+        }
+    }
+}
+
+impl HashParams {
+
+    pub fn from_config (config: &Config) -> HashParams {
+        let default = HashParams::default ();
+        HashParams {
+            memory_kib: config.get_int ("argon2_memory_kib").map (|v| v as u32).unwrap_or (default.memory_kib),
+            iterations: config.get_int ("argon2_iterations").map (|v| v as u32).unwrap_or (default.iterations),
+            parallelism: config.get_int ("argon2_parallelism").map (|v| v as u32).unwrap_or (default.parallelism)
+        }
+    }
+
+}
+
+lazy_static! {
+    static ref HASH_PARAMS: RwLock<HashParams> = RwLock::new (HashParams::default ());
+}
+
+pub fn configure_hashing (params: HashParams) {
+    *HASH_PARAMS.write ().unwrap () = params;
+}
+
+fn hash_params () -> HashParams {
+    *HASH_PARAMS.read ().unwrap ()
+}
+
+fn argon2 () -> Result<Argon2<'static>, PasswordError> {
+    let params = hash_params ();
+    Ok(Argon2::new (
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new (params.memory_kib, params.iterations, params.parallelism, None)?
+    ))
+}
+
+#[derive(Debug)]
+pub enum PasswordError {
+    Bcrypt(BcryptError),
+    Argon2(argon2::password_hash::Error),
+}
+
+impl From<BcryptError> for PasswordError {
+    fn from(e: BcryptError) -> Self {
+        PasswordError::Bcrypt(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for PasswordError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        PasswordError::Argon2(e)
+    }
+}
+
+impl From<argon2::Error> for PasswordError {
+    fn from(e: argon2::Error) -> Self {
+        PasswordError::Argon2(e.into())
+    }
+}
+
 pub trait IsUser {
 
     fn id (&self) -> i64;
@@ -152,7 +395,7 @@ impl<U: DBUser> IsUser for U {
             users_contacts_join::table
                 .filter (users_contacts_join::user_id.eq(self.id ()))
                 .inner_join (contacts::table)
-                .load::<((i64, i64), Contact)> (db)?
+                .load::<(UserContactRelation, Contact)> (db)?
                 .into_iter ()
                 .map (|descriptor| descriptor.1)
                 .collect::<Vec<Contact>> ()
@@ -160,6 +403,7 @@ impl<U: DBUser> IsUser for U {
     }
 
     fn delete(&self, db: &DefaultConnection) -> Result<usize, diesel::result::Error> {
+        hand_over_contacts(self.id (), db)?;
         diesel::delete(
             users::table
                 .find(self.id ())
@@ -217,4 +461,53 @@ impl<T> From<UserId> for ForUser<T> {
     fn from(user: UserId) -> Self {
         ForUser (user.0, PhantomData)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn new_user (password: &str) -> NewUser {
+        NewUser::new ("alice".to_string (), "alice@example.com".to_string (), password.to_string ())
+    }
+
+    #[test]
+    fn argon2_hashes_compare_against_their_password () {
+        let stored = new_user ("hunter2").salt ().unwrap ();
+
+        assert!(stored.password ().starts_with (ARGON2_PREFIX));
+        assert!(stored.password_cmp (&new_user ("hunter2")).unwrap ());
+        assert!(!stored.password_cmp (&new_user ("hunter3")).unwrap ());
+        assert!(!stored.needs_rehash ());
+    }
+
+    #[test]
+    fn bcrypt_hashes_still_compare_but_need_a_rehash () {
+        let stored = new_user (&bcrypt::hash ("hunter2", 4).unwrap ());
+
+        assert!(stored.password_cmp (&new_user ("hunter2")).unwrap ());
+        assert!(!stored.password_cmp (&new_user ("hunter3")).unwrap ());
+        assert!(stored.needs_rehash ());
+    }
+
+    #[test]
+    fn argon2_hashes_with_other_params_need_a_rehash () {
+        let current = hash_params ();
+        let weaker = Argon2::new (
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new (current.memory_kib / 2, current.iterations, current.parallelism, None).unwrap ()
+        );
+        let salt = SaltString::generate (&mut OsRng);
+        let stored = new_user (&weaker.hash_password (b"hunter2", &salt).unwrap ().to_string ());
+
+        assert!(stored.password_cmp (&new_user ("hunter2")).unwrap ());
+        assert!(stored.needs_rehash ());
+    }
+
+    #[test]
+    fn malformed_hashes_need_a_rehash () {
+        assert!(new_user ("$argon2id$garbage").needs_rehash ());
+    }
+}
@@ -0,0 +1,89 @@
+use std::{error::Error, path::PathBuf, sync::Arc, thread::{self, sleep, JoinHandle}, time::Duration};
+
+use rocket::config::Config;
+
+use crate::db::{DBPool, DefaultConnection, Register, outbox::{Mail, NewMail}};
+use self::transport::{Maildir, Smtp, Transport};
+
+pub mod transport;
+
+pub const DEFAULT_POLL_SECONDS: i64 = 10;
+pub const BATCH_SIZE: i64 = 20;
+
+fn transport (config: &Config) -> Result<Arc<dyn Transport>, Box<dyn Error>> {
+    let from = config.get_str("mail_from").unwrap_or("contactive@localhost").to_string();
+
+    Ok(match config.get_str("mail_transport").unwrap_or("file") {
+        "smtp" => {
+            let host = config.get_str("smtp_host")?;
+            let credentials = match (config.get_string("smtp_username"), config.get_string("smtp_password")) {
+                (Ok(username), Ok(password)) => Some((username, password)),
+                _ => None
+            };
+            Arc::new(Smtp::new(from, host, credentials)?)
+        },
+        "file" => Arc::new(Maildir::new(from, PathBuf::from(config.get_str("mail_dir").unwrap_or("mail")))?),
+        other => return Err(format!("Unknown mail transport {}", other).into())
+    })
+}
+
+// Mails are only ever written to the outbox inside request handlers,
+// a background worker drains it through the configured transport
+pub struct Mailer {
+    pool: DBPool,
+    transport: Arc<dyn Transport>,
+    link_base: String,
+}
+
+impl Mailer {
+
+    pub fn new (config: &Config, pool: &DBPool) -> Mailer {
+        let mailer = Mailer {
+            pool: pool.clone(),
+            transport: transport(config).expect("Could not set up the mail transport"),
+            link_base: config.get_str("mail_link_base").unwrap_or("http://localhost:8000").to_string(),
+        };
+
+        let poll = config.get_int("mail_poll_seconds").unwrap_or(DEFAULT_POLL_SECONDS);
+        mailer.start_worker(Duration::from_secs(poll.max(1) as u64));
+        mailer
+    }
+
+    pub fn link (&self, path: &str) -> String {
+        format!("{}{}", self.link_base.trim_end_matches('/'), path)
+    }
+
+    pub fn enqueue (&self, recipient: &str, subject: &str, body: String, db: &DefaultConnection) -> Result<Mail, diesel::result::Error> {
+        NewMail::new(recipient.to_string(), subject.to_string(), body)
+            .register(db)
+    }
+
+    // Each result is recorded on its own, a mail that was sent stays sent whatever happens to the rest of the batch
+    fn deliver_pending (pool: &DBPool, transport: &Arc<dyn Transport>) -> Result<(), Box<dyn Error>> {
+        let db = pool.get()?;
+        for mail in Mail::claim_pending(BATCH_SIZE, &db)? {
+            match transport.send(&mail) {
+                Ok(()) => { mail.mark_sent(&db)?; },
+                Err(e) => {
+                    println!("\t=>\u{001b}[1;31m Could not deliver mail {}: {:?}\u{001b}[0m", mail.id, e);
+                    mail.mark_failed(e.to_string(), &db)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_worker (&self, poll: Duration) -> JoinHandle<()> {
+        let pool = self.pool.clone();
+        let transport = self.transport.clone();
+        thread::spawn(move || {
+            loop {
+                if let Err(e) = Mailer::deliver_pending(&pool, &transport) {
+                    println!("\t=>\u{001b}[1;31m Outbox: {:?}\u{001b}[0m", e);
+                }
+                sleep(poll);
+            }
+        })
+    }
+
+}
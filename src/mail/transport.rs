@@ -0,0 +1,90 @@
+use std::{error::Error, fs, path::PathBuf, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+
+use lettre::{SendableEmail, SmtpClient, SmtpTransport, Transport as _, smtp::authentication::Credentials};
+use lettre_email::EmailBuilder;
+
+use crate::db::outbox::Mail;
+
+pub trait Transport: Send + Sync {
+
+    fn send (&self, mail: &Mail) -> Result<(), Box<dyn Error>>;
+
+}
+
+fn build (from: &str, mail: &Mail) -> Result<SendableEmail, Box<dyn Error>> {
+    Ok(EmailBuilder::new()
+        .to(mail.recipient.clone())
+        .from(from.to_string())
+        .subject(mail.subject.clone())
+        .text(mail.body.clone())
+        .build()?
+        .into())
+}
+
+pub struct Smtp {
+    from: String,
+    transport: Mutex<SmtpTransport>,
+}
+
+impl Smtp {
+
+    pub fn new (from: String, host: &str, credentials: Option<(String, String)>) -> Result<Smtp, Box<dyn Error>> {
+        let mut client = SmtpClient::new_simple(host)?;
+        if let Some((username, password)) = credentials {
+            client = client.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Smtp {
+            from,
+            transport: Mutex::new(client.transport())
+        })
+    }
+
+}
+
+impl Transport for Smtp {
+
+    fn send (&self, mail: &Mail) -> Result<(), Box<dyn Error>> {
+        let email = build(&self.from, mail)?;
+        let mut transport = match self.transport.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        transport.send(email)?;
+        Ok(())
+    }
+
+}
+
+// Writes every mail into `<dir>/new` as a maildir message, for tests and local development
+pub struct Maildir {
+    from: String,
+    dir: PathBuf,
+}
+
+impl Maildir {
+
+    pub fn new (from: String, dir: PathBuf) -> Result<Maildir, Box<dyn Error>> {
+        for sub in &["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        Ok(Maildir { from, dir })
+    }
+
+}
+
+impl Transport for Maildir {
+
+    fn send (&self, mail: &Mail) -> Result<(), Box<dyn Error>> {
+        let message = build(&self.from, mail)?.message_to_string()?;
+        let name = format!("{}.{}.contactive",
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            mail.id);
+
+        let tmp = self.dir.join("tmp").join(&name);
+        fs::write(&tmp, message)?;
+        fs::rename(&tmp, self.dir.join("new").join(&name))?;
+        Ok(())
+    }
+
+}
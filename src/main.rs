@@ -13,17 +13,38 @@ extern crate sha2;
 extern crate base64;
 extern crate jwt_simple;
 extern crate time;
-extern crate sorted_vec;
+extern crate rand;
 extern crate chrono;
 extern crate rocket_cors;
+extern crate postgres;
+extern crate lettre;
+extern crate lettre_email;
+extern crate totp_rs;
+extern crate argon2;
 
 pub mod db;
 pub mod routing;
 pub mod verification;
+pub mod mail;
 
 mod tests;
 
 fn main() {
     dotenv().ok();
-    routing::start ().launch ();
+
+    let args = std::env::args().collect::<Vec<String>>();
+    match args.get(1).map(|arg| &arg[..]) {
+        Some("rotate-keys") => {
+            let rocket = rocket::ignite();
+            verification::jwt::keys::rotate (rocket.config())
+                .expect ("Could not rotate the JWT signing keys");
+        },
+        Some("retire-key") => {
+            let rocket = rocket::ignite();
+            let kid = args.get(2).expect ("Usage: contactive retire-key <kid>");
+            verification::jwt::keys::retire (rocket.config(), kid)
+                .expect ("Could not retire the JWT signing key");
+        },
+        _ => { routing::start ().launch (); }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,189 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::Connection;
+use rocket::{State, http::Status};
+use serde::Serialize;
+
+use crate::db::{DBConnection, Delete, QueryById, Update};
+use crate::db::audit::{AuditEntry, AuditFilter};
+use crate::db::session::Session;
+use crate::db::user::{ForUser, UpdateUser, User, UserId};
+use crate::mail::Mailer;
+use crate::verification::permission::{DeleteUsers, ReadUsers, ReadAudit, ResetPasswords, SuspendUsers, UnlockUsers};
+use super::{Authorized, Catch, EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson};
+use super::password::send_password_reset;
+
+pub const PAGE_SIZE: i64 = 50;
+
+#[derive(Serialize)]
+pub struct AdminUser {
+    id: i64,
+    username: String,
+    email: String,
+    email_verified: bool,
+    role: String,
+    suspended_at: Option<NaiveDateTime>,
+    failed_logins: i32,
+    locked_until: Option<NaiveDateTime>,
+}
+
+impl From<User> for AdminUser {
+    fn from(u: User) -> Self {
+        AdminUser {
+            id: u.id,
+            username: u.username,
+            email: u.email,
+            email_verified: u.email_verified,
+            role: u.role,
+            suspended_at: u.suspended_at,
+            failed_logins: u.failed_logins,
+            locked_until: u.locked_until
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AdminUserDetails {
+    #[serde(flatten)]
+    user: AdminUser,
+    contacts_created: i64,
+    contacts_total: i64,
+    history: Vec<AuditEntry>,
+}
+
+#[get("/admin/users?<q>&<page>")]
+pub fn list_users (q: Option<String>, page: Option<i64>, _auth: Authorized<ReadUsers>, db: DBConnection) -> JsonResponse {
+    let offset = page.unwrap_or(0).max(0) * PAGE_SIZE;
+
+    User::search(q.as_deref().unwrap_or(""), offset, PAGE_SIZE, &db)
+        .to_status()?
+        .into_iter()
+        .map(AdminUser::from)
+        .collect::<Vec<AdminUser>>()
+        .to_json()
+}
+
+#[get("/admin/users/<id>")]
+pub fn get_user (id: i64, _auth: Authorized<ReadUsers>, db: DBConnection) -> JsonResponse {
+    let user = User::query_by_id(id, &db)
+        .to_status()?;
+
+    let (contacts_created, contacts_total) = user.contact_counts(&db)
+        .to_status()?;
+
+    AdminUserDetails {
+        history: AuditEntry::about(id, &db)
+            .to_status()?,
+        user: user.into(),
+        contacts_created,
+        contacts_total
+    }.to_json()
+}
+
+#[post("/admin/users/<id>/suspend")]
+pub fn suspend_user (id: i64, auth: Authorized<SuspendUsers>, db: DBConnection) -> EmptyResponse {
+    if *auth.0 == id {
+        return Err(Status::Forbidden)
+    }
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        UpdateUser {
+            suspended_at: Some(Some(Utc::now().naive_utc())),
+            ..Default::default()
+        }.update(&db, id)?;
+
+        Session::end_all(UserId::new(id), &db)?;
+        AuditEntry::record(auth.0, "user.suspend", Some(id), None, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+#[post("/admin/users/<id>/reactivate")]
+pub fn reactivate_user (id: i64, auth: Authorized<SuspendUsers>, db: DBConnection) -> EmptyResponse {
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        UpdateUser {
+            suspended_at: Some(None),
+            ..Default::default()
+        }.update(&db, id)?;
+
+        AuditEntry::record(auth.0, "user.reactivate", Some(id), None, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+// Clears the failed login count along with any lockout it caused
+#[post("/admin/users/<id>/unlock")]
+pub fn unlock_user (id: i64, auth: Authorized<UnlockUsers>, db: DBConnection) -> EmptyResponse {
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        if User::unlock(id, &db)? == 0 {
+            return Err(diesel::result::Error::NotFound)
+        }
+
+        AuditEntry::record(auth.0, "user.unlock", Some(id), None, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+// Signs the user out everywhere and mails them a reset link
+#[post("/admin/users/<id>/password-reset")]
+pub fn force_password_reset (id: i64, mailer: State<Mailer>, auth: Authorized<ResetPasswords>, db: DBConnection) -> EmptyResponse {
+    let user = User::query_by_id(id, &db)
+        .to_status()?;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        Session::end_all(UserId::new(id), &db)?;
+        send_password_reset(&user, &mailer, &db)?;
+        AuditEntry::record(auth.0, "user.password_reset", Some(id), None, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+#[delete("/admin/users/<id>")]
+pub fn delete_user (id: i64, auth: Authorized<DeleteUsers>, db: DBConnection) -> EmptyResponse {
+    if *auth.0 == id {
+        return Err(Status::Forbidden)
+    }
+
+    let user = User::query_by_id(id, &db)
+        .to_status()?;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        ForUser::<User>::from(UserId::new(id)).delete(&db, id)?;
+        AuditEntry::record(auth.0, "user.delete", Some(id), Some(format!("{} <{}>", user.username, user.email)), &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+fn parse_time (value: Option<String>) -> Result<Option<NaiveDateTime>, Status> {
+    value.map(|value| NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S"))
+        .transpose()
+        .catch(Status::UnprocessableEntity)
+}
+
+// Times are UTC, formatted as `2026-10-18T14:00:00`
+#[get("/admin/audit?<actor>&<user>&<contact>&<action>&<since>&<until>&<page>")]
+pub fn query_audit (actor: Option<i64>, user: Option<i64>, contact: Option<i64>,
+                    action: Option<String>, since: Option<String>, until: Option<String>,
+                    page: Option<i64>, _auth: Authorized<ReadAudit>, db: DBConnection) -> JsonResponse {
+    let filter = AuditFilter {
+        actor,
+        user,
+        contact,
+        action,
+        since: parse_time(since)?,
+        until: parse_time(until)?
+    };
+
+    AuditEntry::query(&filter, page.unwrap_or(0), &db)
+        .to_status()?
+        .to_json()
+}
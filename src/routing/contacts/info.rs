@@ -1,73 +1,106 @@
-use rocket::{State, http::Status};
+use rocket::http::Status;
 use rocket_contrib::json::Json;
 use serde::{ Serialize, Deserialize };
 
-use crate::{db::{DBState, contact::{Contact, IsContact, info::{BareInfo, Info}}}, routing::{Catch, EmptyResponse, JsonResponse, SUCCESS, ToJson}};
+use crate::{db::{DBConnection, contact::{Contact, IsContact, info::{BareInfo, Info}}}, routing::{Catch, EmptyResponse, JsonResponse, ToJson}};
+use diesel::Connection;
 use crate::routing::StatusCatch;
 use crate::db::contact::info::{InfoFragment, InfoSection, Jurisdiction};
 use std::collections::HashMap;
 use crate::db::{DefaultConnection, Delete};
 use crate::db::user::{UserId, ForUser};
+use crate::db::Register;
+use crate::db::audit::{NewAuditEntry, summary};
+use crate::db::contact::{ContactDescriptor, ContactPermission};
+use super::{ensure_access, hide_local};
+use crate::routing::ViaAccessToken;
+use crate::db::contact::Visibility;
 
 #[get("/info/<contact>")]
-pub fn get_info (db: State<DBState>, contact: i64,
-                 user: UserId) -> JsonResponse {
+pub fn get_info (contact: i64,
+                 user: UserId, db: DBConnection, via_token: ViaAccessToken) -> JsonResponse {
     let factory = ForUser::<Contact>::from(user);
 
-    let contact = factory.query_by_id(contact, &**db)
+    let contact = factory.query_by_id(contact, &db)
         .catch(Status::InternalServerError)?;
 
+    if via_token.0 && contact.visibility() == Visibility::Local {
+        return Err(Status::NotFound)
+    }
+
     contact
-        .get_all_info (&**db)
+        .get_all_info (&db)
         .to_status()?
         .to_json ()
 }
 
 fn _check_post_auth (db: &DefaultConnection, user: UserId, contact_id: i64) -> Result<(), Status> {
-    ForUser::<Contact>::from(user).query_by_id(contact_id, db)
-        .to_status()?;
+    ensure_access(user, contact_id, ContactPermission::Editor, db)
+}
+
+fn _info_summary (db: &DefaultConnection, contact: i64) -> Result<Option<String>, diesel::result::Error> {
+    Ok(summary(&ContactDescriptor(contact).get_all_info(db)?))
+}
+
+fn _audit_info (db: &DefaultConnection, user: UserId, contact: i64, action: &str, before: Option<String>) -> Result<(), diesel::result::Error> {
+    NewAuditEntry {
+        actor_id: Some(*user),
+        action: action.to_string(),
+        target_contact_id: Some(contact),
+        before_state: before,
+        after_state: _info_summary(db, contact)?,
+        ..Default::default()
+    }.register(db)?;
 
     Ok(())
 }
 
-fn _post_info (db: State<DBState>, info: Info, user: UserId) -> EmptyResponse {
+fn _post_info (db: DBConnection, info: Info, user: UserId, via_token: ViaAccessToken) -> EmptyResponse {
 
-    _check_post_auth(&**db, user, info.contact_id)?;
+    _check_post_auth(&db, user, info.contact_id)?;
+    hide_local(via_token, info.contact_id, &db)?;
 
-    info.register(&db)
-        .catch(Status::InternalServerError)?;
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = _info_summary(&db, info.contact_id)?;
+        info.register(&db)?;
 
-    SUCCESS
+        _audit_info(&db, user, info.contact_id, "info.create", before)
+    }).to_status()
 }
 
 #[post("/info", format = "application/json", data = "<info>")]
-pub fn post_info_by_data (db: State<DBState>, info: Json<Info>, user: UserId) -> EmptyResponse {
-    _post_info (db, info.into_inner(), user)
+pub fn post_info_by_data (info: Json<Info>, user: UserId, db: DBConnection, via_token: ViaAccessToken) -> EmptyResponse {
+    _post_info (db, info.into_inner(), user, via_token)
 }
 
 #[post("/info/<contact>", format = "application/json", data = "<info>")]
-pub fn post_info_by_url (db: State<DBState>, contact: i64, info: Json<BareInfo>, user: UserId) -> EmptyResponse {
+pub fn post_info_by_url (contact: i64, info: Json<BareInfo>, user: UserId, db: DBConnection, via_token: ViaAccessToken) -> EmptyResponse {
     _post_info (db, Info {
         contact_id: contact,
         info: info.clone ()
-    }, user)
+    }, user, via_token)
 }
 
 #[delete("/info/<contact>", format = "application/json", data = "<infosections>")]
-pub fn delete_info(db: State<DBState>, contact: i64,
+pub fn delete_info(contact: i64,
                    infosections: Json<HashMap<String, Option<Vec<String>>>>,
-                   user: UserId) -> EmptyResponse {
-    _check_post_auth(&**db, user, contact)?;
+                   user: UserId, db: DBConnection, via_token: ViaAccessToken) -> EmptyResponse {
+    _check_post_auth(&db, user, contact)?;
+    hide_local(via_token, contact, &db)?;
 
-    _delete_info(&**db, &*infosections, contact, user)?;
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = _info_summary(&db, contact)?;
+        _delete_info(&db, &*infosections, contact, user)?;
 
-    SUCCESS
+        _audit_info(&db, user, contact, "info.delete", before)
+    }).to_status()
 }
 
+// Access to the contact is checked by the caller, before the transaction starts
 fn _delete_info(db: &DefaultConnection,
                 infosections: &HashMap<String, Option<Vec<String>>>,
                 contact: i64,
-                user: UserId) -> EmptyResponse {
+                user: UserId) -> Result<(), diesel::result::Error> {
     let mut sections = vec![];
     let fragments = (&*infosections).into_iter()
         .filter_map(|(k, values)| if let Some(v) = values {
@@ -81,8 +114,7 @@ fn _delete_info(db: &DefaultConnection,
             });
             None
         })
-        .collect::<Result<Vec<Vec<(String, String)>>, diesel::result::Error>>()
-        .to_status()?
+        .collect::<Result<Vec<Vec<(String, String)>>, diesel::result::Error>>()?
         .into_iter()
         .flatten()
         .map(|(key, value)|
@@ -93,17 +125,13 @@ fn _delete_info(db: &DefaultConnection,
             })
         .collect::<Vec<InfoFragment>>();
 
-    Jurisdiction::new(user, fragments, db)
-        .catch(Status::Unauthorized)?
-        .delete(db, ())
-        .to_status()?;
+    Jurisdiction::new(user, fragments, ContactPermission::Editor, db)?
+        .delete(db, ())?;
 
-    Jurisdiction::new(user, sections, db)
-        .catch(Status::Unauthorized)?
-        .delete(db, ())
-        .to_status()?;
+    Jurisdiction::new(user, sections, ContactPermission::Editor, db)?
+        .delete(db, ())?;
 
-    SUCCESS
+    Ok(())
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -113,9 +141,9 @@ pub struct Diff {
 }
 
 #[patch("/info/<contact>", format = "application/json", data = "<infosections>")]
-pub fn patch_info(db: State<DBState>, contact: i64,
+pub fn patch_info(contact: i64,
                    infosections: Json<Diff>,
-                   user: UserId) -> EmptyResponse {
+                   user: UserId, db: DBConnection, via_token: ViaAccessToken) -> EmptyResponse {
 
     let infosections = infosections.into_inner();
 
@@ -124,12 +152,14 @@ pub fn patch_info(db: State<DBState>, contact: i64,
         info: infosections.new
     };
 
-    _check_post_auth(&**db, user, contact)?;
-
-    _delete_info(&**db, &infosections.delete, contact, user)?;
+    _check_post_auth(&db, user, contact)?;
+    hide_local(via_token, contact, &db)?;
 
-    info.register(&**db)
-        .to_status()?;
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = _info_summary(&db, contact)?;
+        _delete_info(&db, &infosections.delete, contact, user)?;
+        info.register(&db)?;
 
-    SUCCESS
+        _audit_info(&db, user, contact, "info.update", before)
+    }).to_status()
 }
\ No newline at end of file
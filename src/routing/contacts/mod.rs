@@ -1,50 +1,161 @@
-use rocket::{State};
+use rocket::http::Status;
 use rocket_contrib::json::Json;
-use crate::db::{DBState, QueryById, Register, contact::Contact, user::{IsUser, User}};
-use super::{JsonResponse, StatusCatch};
+use crate::db::{DBConnection, DefaultConnection, QueryById, Register, contact::Contact, user::{IsUser, User}};
+use super::{Catch, JsonResponse, SUCCESS, StatusCatch, ViaAccessToken, ensure_verified};
 use crate::routing::{ToJson, EmptyResponse};
 use crate::db::{Delete, Update};
-use crate::db::contact::{UpdateContact, PostContact};
+use crate::db::contact::{ContactPermission, UpdateContact, PostContact, UserContactRelation, Visibility};
 use crate::db::user::{UserId, ForUser};
+use crate::db::contact::IsContact;
+use crate::db::audit::{NewAuditEntry, summary};
+use diesel::Connection;
 
 pub mod info;
+pub mod share_links;
+pub mod shares;
 
+// Local contacts stay on the account, integrations never get to sync them
 #[get("/contacts")]
-pub fn get_contacts (db: State<DBState>, user: UserId) -> JsonResponse {
+pub fn get_contacts (user: UserId, db: DBConnection, via_token: ViaAccessToken) -> JsonResponse {
     User::query_by_id (*user, &db)
         .and_then (|user| user.get_contacts(&db)) 
         .to_status()?
+        .into_iter()
+        .filter(|contact| !via_token.0 || contact.visibility() != Visibility::Local)
+        .collect::<Vec<Contact>>()
         .to_json()
 }
 
+// Contacts the user can see but not change answer with 403 rather than 404
+pub fn ensure_access (user: UserId, contact: i64, needed: ContactPermission, db: &DefaultConnection) -> EmptyResponse {
+    let permission = ForUser::<Contact>::from(user).permission(contact, db)
+        .to_status()?;
+
+    if permission < needed {
+        return Err(Status::Forbidden)
+    }
+
+    SUCCESS
+}
+
+// Integrations can't reach local contacts by writing to them either
+pub fn hide_local (via_token: ViaAccessToken, contact: i64, db: &DefaultConnection) -> EmptyResponse {
+    if via_token.0 && Contact::force_get_by_id(contact, db).to_status()?.visibility() == Visibility::Local {
+        return Err(Status::NotFound)
+    }
+
+    SUCCESS
+}
+
+// Icons are left out, the log only needs to tell which contact it was and how it was shared
+pub fn contact_summary (contact: &Contact) -> Option<String> {
+    summary(&serde_json::json!({
+        "name": contact.name,
+        "visibility": i16::from(contact.visibility()),
+        "creator": contact.creator,
+        "icon": contact.icon.is_some()
+    }))
+}
+
 #[post("/contacts", format = "application/json", data = "<contacts>")]
-pub fn add_contacts (db: State<DBState>, contacts: Json<Vec<PostContact>>, user: UserId) -> JsonResponse {
+pub fn add_contacts (contacts: Json<Vec<PostContact>>, user: UserId, db: DBConnection) -> JsonResponse {
+    let visibilities = contacts.iter()
+        .map(|contact| contact.visibility())
+        .collect::<Result<Vec<Visibility>, i16>>()
+        .catch(Status::UnprocessableEntity)?;
+
+    if visibilities.contains(&Visibility::Public) {
+        ensure_verified(user, &db)?;
+    }
+
     let factory = ForUser::<PostContact>::from(user);
-    contacts.into_inner()
-        .into_iter ()
-        .map(|contact|
-            factory.relate(contact)
-                .register (&db))
-        .collect::<Result<Vec<Contact>, diesel::result::Error>> ()
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        contacts.into_inner()
+            .into_iter ()
+            .map(|contact| {
+                let contact = factory.relate(contact)
+                    .register (&db)?;
+
+                NewAuditEntry {
+                    actor_id: Some(*user),
+                    action: "contact.create".to_string(),
+                    target_contact_id: Some(contact.id),
+                    after_state: contact_summary(&contact),
+                    ..Default::default()
+                }.register(&db)?;
+
+                Ok(contact)
+            })
+            .collect::<Result<Vec<Contact>, diesel::result::Error>> ()
+    })
         .to_status()?
         .to_json ()
 }
 
 #[delete("/contacts/<id>")]
-pub fn delete_contact (db: State<DBState>, id: i64, user: UserId) -> EmptyResponse {
+pub fn delete_contact (id: i64, user: UserId, db: DBConnection, via_token: ViaAccessToken) -> EmptyResponse {
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+    hide_local(via_token, id, &db)?;
+
+    let factory = ForUser::<Contact>::from(user);
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = factory.query_by_id(id, &db)?;
+        factory.delete(&db, id)?;
 
-    ForUser::<Contact>::from(user).delete(&**db, id).to_status()?;
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "contact.delete".to_string(),
+            target_contact_id: Some(id),
+            before_state: contact_summary(&before),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
 
     Ok(())
 }
 
 #[patch("/contacts/<id>", format = "application/json", data = "<contact>")]
-pub fn edit_contact (db: State<DBState>, id: i64, contact: Json<UpdateContact>, user: UserId) -> JsonResponse {
+pub fn edit_contact (id: i64, contact: Json<UpdateContact>, user: UserId, db: DBConnection, via_token: ViaAccessToken) -> JsonResponse {
+    let visibility = contact.visibility()
+        .catch(Status::UnprocessableEntity)?;
+
+    if visibility == Some(Visibility::Public) {
+        ensure_verified(user, &db)?;
+    }
+
+    // Who gets to see the contact is left to its co-owners
+    let needed = match visibility {
+        Some(_) => ContactPermission::CoOwner,
+        None => ContactPermission::Editor
+    };
+    ensure_access(user, id, needed, &db)?;
+    hide_local(via_token, id, &db)?;
+
+    // A contact has to be unshared before it can be made local
+    if visibility == Some(Visibility::Local)
+        && UserContactRelation::count_of_contact(id, &db).to_status()? > 1 {
+        return Err(Status::Conflict)
+    }
+
     let factory: ForUser<UpdateContact> = user.into();
-    factory.get(contact.into_inner())
-        .update(&**db, id)
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = ForUser::<Contact>::from(user).query_for(id, needed, &db)?;
+        let after = factory.get(contact.into_inner())
+            .update(&db, id)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "contact.update".to_string(),
+            target_contact_id: Some(id),
+            before_state: contact_summary(&before),
+            after_state: contact_summary(&after),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(after)
+    })
         .to_status()?
         .to_json()
 }
-
-
@@ -0,0 +1,155 @@
+use chrono::NaiveDateTime;
+use diesel::Connection;
+use jwt_simple::prelude::Duration;
+use rocket::{State, http::Status};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, Register};
+use crate::db::audit::{NewAuditEntry, summary};
+use crate::db::contact::{Contact, ContactPermission, IsContact};
+use crate::db::share_link::{NewShareLink, ShareLink};
+use crate::db::user::{ForUser, User, UserId};
+use crate::verification::jwt::persona_jwt::{ContactJwtHandler, SHARE_LINK_DEFAULT_HOURS, ShareLinkError};
+use super::ensure_access;
+use crate::routing::{EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson, ToStatus, VerifiedUser};
+
+pub const SHARE_LINK_MAX_HOURS: u64 = 24 * 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CreateShareLink {
+    pub expires_in_hours: Option<u64>,
+    pub max_uses: Option<i32>,
+    // Username or email of the only user allowed to accept the link
+    pub recipient: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedShareLink {
+    #[serde(flatten)]
+    pub info: ShareLink,
+    // Only ever shown here, it can't be recovered from the link's row
+    pub token: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ShareLinkToken {
+    pub token: String,
+}
+
+#[post("/contacts/<id>/share-links", format = "application/json", data = "<create>")]
+pub fn create_share_link (id: i64, create: Json<CreateShareLink>, links: State<ContactJwtHandler>, user: VerifiedUser, db: DBConnection) -> JsonResponse {
+    let create = create.into_inner();
+    let user = user.0;
+    let hours = create.expires_in_hours.unwrap_or(SHARE_LINK_DEFAULT_HOURS);
+
+    if hours < 1 || hours > SHARE_LINK_MAX_HOURS
+        || create.max_uses.map(|max| max < 1).unwrap_or(false) {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+
+    let contact = ForUser::<Contact>::from(user).query_for(id, ContactPermission::CoOwner, &db)
+        .to_status()?;
+
+    if !contact.visibility().can_share() {
+        return Err(Status::Conflict)
+    }
+
+    let recipient_id = match &create.recipient {
+        Some(login) => match User::query_by_login(login.trim(), &db) {
+            Ok(recipient) => Some(recipient.id),
+            Err(diesel::result::Error::NotFound) => return Err(Status::UnprocessableEntity),
+            Err(e) => return Err(e.to_status())
+        },
+        None => None
+    };
+
+    let (token, claims) = links.mint(contact.id, Duration::from_hours(hours))
+        .to_status()?;
+    let expires = claims.expires_at
+        .ok_or(Status::InternalServerError)?;
+
+    let info = db.transaction::<_, diesel::result::Error, _>(|| {
+        let info = NewShareLink {
+            id: claims.jwt_id.clone().unwrap_or_default(),
+            contact_id: contact.id,
+            creator: *user,
+            recipient_id,
+            max_uses: create.max_uses,
+            expires: NaiveDateTime::from_timestamp(expires.as_secs() as i64, 0),
+        }.register(&db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "share_link.create".to_string(),
+            target_contact_id: Some(contact.id),
+            details: Some(info.id.clone()),
+            after_state: summary(&info),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(info)
+    }).to_status()?;
+
+    CreatedShareLink {
+        info,
+        token
+    }.to_json()
+}
+
+// The caller's links that can still be accepted, optionally for a single contact
+#[get("/share-links?<contact>")]
+pub fn get_share_links (contact: Option<i64>, user: UserId, db: DBConnection) -> JsonResponse {
+    ShareLink::outstanding(user, contact, &db)
+        .to_status()?
+        .to_json()
+}
+
+#[delete("/share-links/<id>")]
+pub fn revoke_share_link (id: String, user: UserId, db: DBConnection) -> EmptyResponse {
+    let link = ShareLink::query_by_id(&id, &db)
+        .to_status()?;
+
+    if link.creator != *user {
+        return Err(Status::NotFound)
+    }
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        ShareLink::revoke(user, &id, &db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "share_link.revoke".to_string(),
+            target_contact_id: Some(link.contact_id),
+            details: Some(id.clone()),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+// Tokens travel in the body, paths end up in access logs
+#[post("/share-links/accept", format = "application/json", data = "<token>")]
+pub fn accept_share_link (token: Json<ShareLinkToken>, links: State<ContactJwtHandler>, user: UserId, db: DBConnection) -> JsonResponse {
+    db.transaction::<_, ShareLinkError, _>(|| {
+        let (contact, added) = links.accept(&token.token, user, &db)?;
+
+        if added {
+            NewAuditEntry {
+                actor_id: Some(*user),
+                action: "share_link.accept".to_string(),
+                target_user_id: Some(*user),
+                target_contact_id: Some(contact.id),
+                ..Default::default()
+            }.register(&db)?;
+        }
+
+        Ok(contact)
+    })
+        .to_status()?
+        .to_json()
+}
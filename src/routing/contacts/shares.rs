@@ -0,0 +1,129 @@
+use diesel::Connection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, Register};
+use crate::db::audit::{NewAuditEntry, summary};
+use crate::db::contact::{Contact, ContactPermission, IsContact, UserContactRelation};
+use crate::db::user::{User, UserId};
+use super::ensure_access;
+use crate::routing::{EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson, ToStatus, VerifiedUser};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ShareContact {
+    // Username or email
+    pub user: String,
+    pub permission: ContactPermission,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Share {
+    pub user_id: i64,
+    pub username: String,
+    pub permission: ContactPermission,
+}
+
+impl From<(UserContactRelation, User)> for Share {
+    fn from((relation, user): (UserContactRelation, User)) -> Self {
+        Share {
+            user_id: user.id,
+            username: user.username,
+            permission: relation.permission()
+        }
+    }
+}
+
+#[get("/contacts/<id>/shares")]
+pub fn get_shares (id: i64, user: UserId, db: DBConnection) -> JsonResponse {
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+
+    UserContactRelation::of_contact(id, &db)
+        .to_status()?
+        .into_iter()
+        .map(Share::from)
+        .collect::<Vec<Share>>()
+        .to_json()
+}
+
+#[post("/contacts/<id>/shares", format = "application/json", data = "<share>")]
+pub fn share_contact (id: i64, share: Json<ShareContact>, user: VerifiedUser, db: DBConnection) -> JsonResponse {
+    let share = share.into_inner();
+    let user = user.0;
+
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+
+    let contact = Contact::force_get_by_id(id, &db)
+        .to_status()?;
+
+    if !contact.visibility().can_share() {
+        return Err(Status::Conflict)
+    }
+
+    let recipient = match User::query_by_login(share.user.trim(), &db) {
+        Ok(recipient) => recipient,
+        Err(diesel::result::Error::NotFound) => return Err(Status::UnprocessableEntity),
+        Err(e) => return Err(e.to_status())
+    };
+
+    // The creator stays a co-owner, and nobody changes their own access
+    if recipient.id == *user || recipient.id == contact.creator {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let relation = UserContactRelation (
+            recipient.id,
+            contact.id,
+            share.permission.into()
+        ).grant(&db)?;
+        let share = Share::from((relation, recipient.clone()));
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "contact.share".to_string(),
+            target_user_id: Some(recipient.id),
+            target_contact_id: Some(contact.id),
+            after_state: summary(&share),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(share)
+    })
+        .to_status()?
+        .to_json()
+}
+
+// Co-owners can take anybody off a contact, everyone else only themselves
+#[delete("/contacts/<id>/shares/<user_id>")]
+pub fn unshare_contact (id: i64, user_id: i64, user: UserId, db: DBConnection) -> EmptyResponse {
+    let needed = if user_id == *user {
+        ContactPermission::Viewer
+    } else {
+        ContactPermission::CoOwner
+    };
+    ensure_access(user, id, needed, &db)?;
+
+    let contact = Contact::force_get_by_id(id, &db)
+        .to_status()?;
+
+    // Without its creator a contact is deleted instead
+    if user_id == contact.creator {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        UserContactRelation::revoke(user_id, contact.id, &db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "contact.unshare".to_string(),
+            target_user_id: Some(user_id),
+            target_contact_id: Some(contact.id),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
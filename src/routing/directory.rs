@@ -0,0 +1,112 @@
+use diesel::Connection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::db::{DBConnection, Register};
+use crate::db::audit::{NewAuditEntry, summary};
+use crate::db::contact::{Contact, ContactPermission, NewContact, Visibility, info::Info};
+use crate::db::contact::directory::{DirectoryEntry, HiddenKey, MIN_QUERY_CHARS};
+use crate::db::user::{ForUser, UserId};
+use super::contacts::{contact_summary, ensure_access};
+use super::{JsonResponse, StatusCatch, ToJson};
+
+#[get("/directory?<q>&<page>")]
+pub fn search_directory (q: Option<String>, page: Option<i64>, _user: UserId, db: DBConnection) -> JsonResponse {
+    let q = q.as_deref().unwrap_or("").trim();
+
+    // The directory can be searched, not browsed
+    if q.chars().count() < MIN_QUERY_CHARS {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    DirectoryEntry::search(q, page.unwrap_or(0), &db)
+        .to_status()?
+        .to_json()
+}
+
+// Public contacts can be read by anybody signed in, private and local ones look like they don't exist
+#[get("/directory/<id>")]
+pub fn get_directory_entry (id: i64, _user: UserId, db: DBConnection) -> JsonResponse {
+    let contact = Contact::query_public(id, &db)
+        .to_status()?;
+
+    DirectoryEntry::of(contact, &db)
+        .to_status()?
+        .to_json()
+}
+
+// Saving makes a private copy of what the directory shows, so hidden keys stay hidden
+#[post("/directory/<id>/save")]
+pub fn save_from_directory (id: i64, user: UserId, db: DBConnection) -> JsonResponse {
+    let contact = Contact::query_public(id, &db)
+        .to_status()?;
+
+    if contact.creator == *user {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    let entry = DirectoryEntry::of(contact, &db)
+        .to_status()?;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let copy = ForUser::<NewContact>::from(user)
+            .new(entry.name, entry.icon, Visibility::Private)
+            .register(&db)?;
+
+        Info {
+            contact_id: copy.id,
+            info: entry.info
+        }.register(&db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "directory.save".to_string(),
+            target_contact_id: Some(copy.id),
+            details: Some(id.to_string()),
+            after_state: contact_summary(&copy),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(copy)
+    })
+        .to_status()?
+        .to_json()
+}
+
+#[get("/contacts/<id>/hidden-keys")]
+pub fn get_hidden_keys (id: i64, user: UserId, db: DBConnection) -> JsonResponse {
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+
+    HiddenKey::of_contact(id, &db)
+        .to_status()?
+        .to_json()
+}
+
+#[put("/contacts/<id>/hidden-keys", format = "application/json", data = "<keys>")]
+pub fn set_hidden_keys (id: i64, keys: Json<Vec<String>>, user: UserId, db: DBConnection) -> JsonResponse {
+    ensure_access(user, id, ContactPermission::CoOwner, &db)?;
+
+    let keys = keys.into_inner()
+        .into_iter()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
+        .collect::<Vec<String>>();
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = HiddenKey::of_contact(id, &db)?;
+        let after = HiddenKey::replace_for(id, keys, &db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "contact.hidden_keys".to_string(),
+            target_contact_id: Some(id),
+            before_state: summary(&before),
+            after_state: summary(&after),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(after)
+    })
+        .to_status()?
+        .to_json()
+}
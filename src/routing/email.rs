@@ -0,0 +1,69 @@
+use chrono::Utc;
+use diesel::Connection;
+use rocket::{State, http::Status};
+
+use crate::db::{DBConnection, DefaultConnection, QueryById, Register};
+use crate::db::token::{EmailVerification, NewEmailVerification};
+use crate::db::user::{User, UserId};
+use crate::mail::Mailer;
+use crate::verification::{fingerprint, random_token};
+use super::{EmptyResponse, SUCCESS, StatusCatch, ToStatus};
+
+pub const VERIFICATION_TOKEN_HOURS: i64 = 48;
+
+pub fn send_email_verification (user: &User, mailer: &Mailer, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+    let secret = random_token(32);
+
+    NewEmailVerification {
+        token_hash: fingerprint(&secret),
+        user_id: user.id,
+        email: user.email.clone(),
+        expires: Utc::now().naive_utc() + chrono::Duration::hours(VERIFICATION_TOKEN_HOURS)
+    }.register(db)?;
+
+    mailer.enqueue(&user.email, "Verify your Contactive email address", format!(
+        "Hello {},\n\nPlease confirm that this is your email address by following the link below \
+        within {} hours:\n\n{}\n\n\
+        If you didn't create a Contactive account, you can safely ignore this mail.\n",
+        user.username,
+        VERIFICATION_TOKEN_HOURS,
+        mailer.link(&format!("/verify-email/{}", secret))
+    ), db)?;
+
+    Ok(())
+}
+
+#[get("/verify-email/<token>")]
+pub fn verify_email (token: String, db: DBConnection) -> EmptyResponse {
+    let token_hash = fingerprint(&token);
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let (user_id, email) = EmailVerification::consume(&token_hash, &db)?;
+
+        if User::mark_email_verified(user_id, &email, &db)? == 0 {
+            return Err(diesel::result::Error::NotFound)
+        }
+
+        Ok(())
+    }).map_err(|e| match e {
+        diesel::result::Error::NotFound => Status::Unauthorized,
+        e => e.to_status()
+    })?;
+
+    SUCCESS
+}
+
+#[post("/verify-email/resend")]
+pub fn resend_verification (mailer: State<Mailer>, user: UserId, db: DBConnection) -> EmptyResponse {
+    let user = User::query_by_id(*user, &db)
+        .to_status()?;
+
+    if user.email_verified {
+        return Err(Status::Conflict)
+    }
+
+    send_email_verification(&user, &mailer, &db)
+        .to_status()?;
+
+    SUCCESS
+}
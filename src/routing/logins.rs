@@ -0,0 +1,88 @@
+use chrono::Utc;
+
+use crate::db::{DBConnection, DefaultConnection, Register};
+use crate::db::audit::NewAuditEntry;
+use crate::db::login_event::{LoginEvent, NewLoginEvent};
+use crate::db::user::{User, UserId};
+use crate::mail::Mailer;
+use crate::verification::ClientInfo;
+use super::{JsonResponse, StatusCatch, ToJson};
+
+pub fn record_failed_login (user: &User, client: &ClientInfo, failure: &str, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+    // Attempts on a locked account aren't even checked, the first one of each lockout says all there is to say
+    if failure == "locked" {
+        let latest = LoginEvent::latest(user.id, db)?;
+        if latest.map(|event| event.failure.as_deref() == Some(failure)).unwrap_or(false) {
+            return Ok(())
+        }
+    }
+
+    // Nobody is authenticated yet, so there is no actor
+    NewAuditEntry {
+        action: "user.login_failed".to_string(),
+        target_user_id: Some(user.id),
+        details: Some(failure.to_string()),
+        ..Default::default()
+    }.register(db)?;
+
+    NewLoginEvent {
+        user_id: user.id,
+        success: false,
+        failure: Some(failure.to_string()),
+        ip: client.ip.clone(),
+        network: client.network(),
+        user_agent: client.user_agent.clone()
+    }.register(db)?;
+
+    LoginEvent::prune_failures(user.id, db)?;
+
+    Ok(())
+}
+
+// Warns the owner when a login comes from a browser or network the account has never used before
+pub fn record_login (user: &User, client: &ClientInfo, mailer: &Mailer, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+    let network = client.network();
+
+    let suspicious = LoginEvent::has_succeeded_before(user.id, db)?
+        && (!LoginEvent::user_agent_seen(user.id, &client.user_agent, db)?
+            || !LoginEvent::network_seen(user.id, &network, db)?);
+
+    NewLoginEvent {
+        user_id: user.id,
+        success: true,
+        failure: None,
+        ip: client.ip.clone(),
+        network,
+        user_agent: client.user_agent.clone()
+    }.register(db)?;
+
+    NewAuditEntry {
+        actor_id: Some(user.id),
+        action: "user.login".to_string(),
+        target_user_id: Some(user.id),
+        details: client.ip.clone(),
+        ..Default::default()
+    }.register(db)?;
+
+    if suspicious {
+        mailer.enqueue(&user.email, "New sign-in to your Contactive account", format!(
+            "Hello {},\n\nYour account was just signed in to from a device or network we haven't seen before:\n\n\
+            Time: {} UTC\nAddress: {}\nBrowser: {}\n\n\
+            If this was you, there is nothing to do. If it wasn't, change your password right away \
+            and sign out the sessions you don't recognise.\n",
+            user.username,
+            Utc::now().format("%Y-%m-%d %H:%M"),
+            client.ip.as_deref().unwrap_or("unknown"),
+            client.user_agent.as_deref().unwrap_or("unknown")
+        ), db)?;
+    }
+
+    Ok(())
+}
+
+#[get("/me/logins")]
+pub fn get_logins (user: UserId, db: DBConnection) -> JsonResponse {
+    LoginEvent::of_user(user, &db)
+        .to_status()?
+        .to_json()
+}
@@ -0,0 +1,171 @@
+use diesel::Connection;
+use rocket::{State, http::{Cookies, Status}};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, QueryById};
+use crate::db::mfa::{NewUserMfa, RecoveryCode, UserMfa};
+use crate::db::user::{Password, User, UserId};
+use crate::derive_password;
+use crate::verification::{ClientInfo, jwt::LoginHandler};
+use crate::verification::totp::{check_code, generate_recovery_codes, generate_secret, recovery_code_hash, totp};
+use crate::verification::throttle::Throttle;
+use crate::mail::Mailer;
+use super::logins::{record_failed_login, record_login};
+use super::{Catch, EmptyResponse, JsonResponse, LoginResponse, Rejection, SUCCESS, StatusCatch, ToJson, token_response};
+
+#[derive(Serialize)]
+pub struct MfaEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MfaCode {
+    pub code: String
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DisableMfa {
+    pub password: String,
+    pub code: String
+}
+
+derive_password! (DisableMfa);
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MfaLogin {
+    pub challenge: String,
+    pub code: String
+}
+
+#[post("/me/mfa")]
+pub fn enroll_mfa (user: UserId, db: DBConnection) -> JsonResponse {
+    let user = User::query_by_id(*user, &db)
+        .to_status()?;
+
+    if UserMfa::is_enabled(user.id, &db).to_status()? {
+        return Err(Status::Conflict)
+    }
+
+    let mfa = UserMfa::enroll(NewUserMfa {
+        user_id: user.id,
+        secret: generate_secret()
+    }, &db).to_status()?;
+
+    let otpauth_uri = totp(&mfa.secret, &user)
+        .catch(Status::UnprocessableEntity)?
+        .get_url();
+
+    MfaEnrollment {
+        secret: mfa.secret,
+        otpauth_uri
+    }.to_json()
+}
+
+#[post("/me/mfa/confirm", format = "application/json", data = "<code>")]
+pub fn confirm_mfa (code: Json<MfaCode>, user: UserId, db: DBConnection) -> JsonResponse {
+    let user = User::query_by_id(*user, &db)
+        .to_status()?;
+
+    let mfa = match UserMfa::of_user(user.id, &db).to_status()? {
+        Some(mfa) if !mfa.confirmed => mfa,
+        Some(_) => return Err(Status::Conflict),
+        None => return Err(Status::NotFound)
+    };
+
+    if !check_code(&user, &mfa, &code.code, &db).catch(Status::InternalServerError)? {
+        return Err(Status::Unauthorized)
+    }
+
+    let codes = generate_recovery_codes();
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        RecoveryCode::replace_for(user.id, codes.iter()
+            .map(|code| recovery_code_hash(code))
+            .collect(), &db)?;
+        UserMfa::confirm(user.id, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    RecoveryCodes {
+        recovery_codes: codes
+    }.to_json()
+}
+
+#[delete("/me/mfa", format = "application/json", data = "<disable>")]
+pub fn disable_mfa (disable: Json<DisableMfa>, user: UserId, db: DBConnection) -> EmptyResponse {
+    let user = User::query_by_id(*user, &db)
+        .to_status()?;
+
+    let mfa = UserMfa::of_user(user.id, &db)
+        .to_status()?
+        .ok_or(Status::NotFound)?;
+
+    let authorized = user.password_cmp(&disable.encrypt())
+        .catch(Status::InternalServerError)?;
+
+    if !authorized || !check_code(&user, &mfa, &disable.code, &db).catch(Status::InternalServerError)? {
+        return Err(Status::Unauthorized)
+    }
+
+    UserMfa::disable(user.id, &db)
+        .to_status()?;
+
+    SUCCESS
+}
+
+#[post("/login/mfa?<cookie>", format = "application/json", data = "<login>")]
+pub fn login_mfa (login: Json<MfaLogin>, cookie: Option<bool>, client: ClientInfo, db: DBConnection, jwt_key: State<LoginHandler>, throttle: State<Throttle>, mailer: State<Mailer>, cookies: Cookies) -> LoginResponse {
+    let login = login.into_inner();
+
+    throttle.check_address(&client.ip)
+        .map_err(Rejection::RetryAfter)?;
+
+    let challenge = jwt_key.open_challenge(&login.challenge)
+        .catch(Status::Unauthorized)?;
+
+    let user = User::query_by_id(challenge.custom.mfa_user_id, &db)
+        .to_status()?;
+
+    // Codes are short, so guessing them counts against the same limits as passwords
+    if let Err(secs) = throttle.check_account(&user) {
+        record_failed_login(&user, &client, "locked", &db)
+            .to_status()?;
+        return Err(Rejection::RetryAfter(secs))
+    }
+
+    let mfa = match UserMfa::of_user(user.id, &db).to_status()? {
+        Some(mfa) if mfa.confirmed => mfa,
+        _ => return Err(Status::Unauthorized.into())
+    };
+
+    if !check_code(&user, &mfa, &login.code, &db).catch(Status::InternalServerError)? {
+        throttle.address_failed(&client.ip);
+        throttle.account_failed(&user, &db)
+            .to_status()?;
+        record_failed_login(&user, &client, "mfa", &db)
+            .to_status()?;
+        return Err(Status::Unauthorized.into())
+    }
+
+    throttle.account_succeeded(&user, &db)
+        .to_status()?;
+    record_login(&user, &client, &mailer, &db)
+        .to_status()?;
+
+    jwt_key.close_challenge(challenge, login.challenge, &db)
+        .to_status()?;
+
+    println! ("\t=> Second factor is correct");
+
+    let pair = jwt_key.issue(user, &client, &db)
+        .to_status()?;
+
+    Ok(token_response(pair, cookie, cookies, &jwt_key)?)
+}
@@ -1,32 +1,96 @@
-use rocket::{Rocket, http::Status, Request, request::Outcome};
+use rocket::{Rocket, http::Status, Request, request::Outcome, response::{self, Responder, Response}};
 use serde::Serialize;
 use rocket::http::Method;
 use rocket_cors::{AllowedOrigins, CorsOptions};
 
-use crate::verification::jwt::{LoginHandler, Token};
+use crate::verification::jwt::{LoginHandler, Token, persona_jwt::ContactJwtHandler};
 use rocket::request::FromRequest;
-use crate::db::user::UserId;
+use crate::db::user::{HashParams, User, UserId, configure_hashing};
+use crate::db::{DBConnection, DBPool, DefaultConnection, QueryById};
+use crate::verification::{ClientInfo, access_token, client_ip, configure_proxies, cookie, permission::Permission, throttle::Throttle};
+use crate::verification::jwt::refresh::TokenPair;
+use rocket::http::Cookies;
+use crate::db::role::has_permission;
+use std::marker::PhantomData;
+use crate::mail::Mailer;
 
 pub mod user;
+pub mod sessions;
+pub mod password;
+pub mod email;
+pub mod mfa;
+pub mod tokens;
+pub mod roles;
+pub mod admin;
+pub mod logins;
 pub mod contacts;
+pub mod directory;
+pub mod personas;
 
 #[get("/")]
 fn root() -> String {
     format!("Welcome to Rocket on Rust")
 }
 
+#[get("/.well-known/jwks.json")]
+fn jwks(jwt_key: rocket::State<LoginHandler>) -> JsonResponse {
+    jwt_key.keys.jwks().to_json()
+}
+
 pub fn start () -> Rocket {
-    rocket::ignite()
-    .manage(crate::db::DBState::new ())
-    .manage(LoginHandler::new ())
+    let rocket = rocket::ignite();
+    configure_hashing (HashParams::from_config (rocket.config()));
+    configure_proxies (rocket.config());
+    let pool = DBPool::new (rocket.config());
+    let login_handler = LoginHandler::new (rocket.config(), &pool);
+    let mailer = Mailer::new (rocket.config(), &pool);
+    let throttle = Throttle::new (rocket.config());
+    let share_links = ContactJwtHandler::new (rocket.config(), &pool);
+
+    rocket
+    .manage(pool)
+    .manage(login_handler)
+    .manage(mailer)
+    .manage(throttle)
+    .manage(share_links)
     .mount("/", routes![
         root,
+        jwks,
         user::register,
         user::login,
         user::logout,
         user::delete,
         user::me,
         user::renew,
+        user::renew_cookie,
+        user::edit_me,
+        user::change_password,
+        logins::get_logins,
+        sessions::get_sessions,
+        sessions::end_session,
+        sessions::end_other_sessions,
+        password::forgot_password,
+        password::reset_password_form,
+        password::reset_password,
+        email::verify_email,
+        email::resend_verification,
+        mfa::enroll_mfa,
+        mfa::confirm_mfa,
+        mfa::disable_mfa,
+        mfa::login_mfa,
+        tokens::get_tokens,
+        tokens::create_token,
+        tokens::revoke_token,
+        roles::get_roles,
+        roles::assign_role,
+        admin::list_users,
+        admin::get_user,
+        admin::suspend_user,
+        admin::reactivate_user,
+        admin::force_password_reset,
+        admin::unlock_user,
+        admin::delete_user,
+        admin::query_audit,
         contacts::get_contacts,
         contacts::add_contacts,
         contacts::delete_contact,
@@ -36,10 +100,32 @@ pub fn start () -> Rocket {
         contacts::info::post_info_by_url,
         contacts::info::delete_info,
         contacts::info::patch_info,
+        contacts::share_links::create_share_link,
+        contacts::share_links::get_share_links,
+        contacts::share_links::revoke_share_link,
+        contacts::share_links::accept_share_link,
+        contacts::shares::get_shares,
+        contacts::shares::share_contact,
+        contacts::shares::unshare_contact,
+        directory::search_directory,
+        directory::get_directory_entry,
+        directory::save_from_directory,
+        directory::get_hidden_keys,
+        directory::set_hidden_keys,
+        personas::get_personas,
+        personas::get_persona,
+        personas::add_persona,
+        personas::edit_persona,
+        personas::delete_persona,
+        personas::create_persona_token,
+        personas::get_persona_tokens,
+        personas::revoke_persona_token,
+        personas::open_persona,
+        personas::accept_persona,
     ]).attach(CorsOptions::default()
         .allowed_origins(AllowedOrigins::all())
         .allowed_methods(
-            vec![Method::Get, Method::Post, Method::Delete, Method::Patch]
+            vec![Method::Get, Method::Post, Method::Put, Method::Delete, Method::Patch]
                 .into_iter()
                 .map(From::from)
                 .collect(),
@@ -80,6 +166,42 @@ impl<T: ?Sized + Serialize> ToJson for T {}
 
 type EmptyResponse = Result<(), Status>;
 
+// A failed status, or a 429 telling the client how many seconds to back off for
+#[derive(Debug)]
+pub enum Rejection {
+    Status(Status),
+    RetryAfter(u64),
+}
+
+impl From<Status> for Rejection {
+    fn from(status: Status) -> Self {
+        Rejection::Status(status)
+    }
+}
+
+impl<'r> Responder<'r> for Rejection {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Rejection::Status(status) => status.respond_to(request),
+            Rejection::RetryAfter(secs) => Response::build()
+                .status(Status::TooManyRequests)
+                .raw_header("Retry-After", secs.to_string())
+                .ok()
+        }
+    }
+}
+
+type LoginResponse = Result<JsonResponseOk, Rejection>;
+
+// Cookie clients get their tokens set as cookies, everyone else in the body
+fn token_response (pair: TokenPair, use_cookies: Option<bool>, mut cookies: Cookies, jwt_key: &LoginHandler) -> JsonResponse {
+    if use_cookies.unwrap_or(false) {
+        cookie::start_session(&mut cookies, pair, jwt_key.secure_cookies).to_json()
+    } else {
+        pair.to_json()
+    }
+}
+
 const SUCCESS: EmptyResponse = Ok(());
 
 trait Verifier: crate::verification::Verifier {
@@ -90,17 +212,153 @@ trait Verifier: crate::verification::Verifier {
 
 }
 
-impl<'a, 'r> FromRequest<'a, 'r> for UserId {
+fn reject_suspended (user: UserId, db: &DefaultConnection) -> Outcome<UserId, ()> {
+    match User::is_suspended(*user, db) {
+        Ok(false) => Outcome::Success(user),
+        Ok(true) => Outcome::Failure((Status::Forbidden, ())),
+        Err(e) => Outcome::Failure((e.to_status(), ()))
+    }
+}
+
+// For requests authenticated by cookies alone, outside of the `Token` guard
+pub struct CsrfProtected;
+
+impl<'a, 'r> FromRequest<'a, 'r> for CsrfProtected {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        if cookie::csrf_passes(request, &mut request.cookies()) {
+            Outcome::Success(CsrfProtected)
+        } else {
+            Outcome::Failure((Status::Forbidden, ()))
+        }
+    }
+}
+
+pub struct CurrentSession {
+    pub user: UserId,
+    pub sid: String,
+}
+
+impl CurrentSession {
+
+    fn of (request: &Request, token: &Token, db: &DefaultConnection) -> Outcome<CurrentSession, ()> {
         let key = request.guard::<rocket::State::<LoginHandler>>()?;
-        let token = request.guard::<Token>().map_failure(|_| (Status::Unauthorized, ()))?;
 
-        match key.verify_or_respond (&token) {
-            Ok(claims) => Outcome::Success(UserId::new(claims.custom.user_id)),
-            Err(_) => Outcome::Failure((Status::Unauthorized, ()))
-        }
+        let claims = match key.verify_session (token, db).catch(Status::Unauthorized) {
+            Ok(claims) => claims,
+            Err(status) => return Outcome::Failure((status, ()))
+        };
+
+        let user = reject_suspended(UserId::new(claims.custom.user_id), db)?;
+
+        Outcome::Success(CurrentSession {
+            user,
+            sid: claims.custom.sid
+        })
+    }
+
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CurrentSession {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let token = request.guard::<Token>().map_failure(|(status, _)| (status, ()))?;
+
+        DBConnection::with_request(request, |db| CurrentSession::of(request, &token, db))
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserId {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let token = request.guard::<Token>().map_failure(|(status, _)| (status, ()))?;
+
+        DBConnection::with_request(request, |db| {
+            if !access_token::is_access_token(&token.0) {
+                return CurrentSession::of(request, &token, db)
+                    .map(|session| session.user)
+            }
+
+            let route = request.route().and_then(|route| route.name);
+
+            match access_token::authenticate(&token.0, route, db) {
+                Ok(user) => reject_suspended(user, db),
+                Err(e) => Outcome::Failure((e.to_status(), ()))
+            }
+        })
+    }
+}
+
+// Whether the request comes from an integration holding an access token rather than a login
+pub struct ViaAccessToken (pub bool);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ViaAccessToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let via_token = match request.guard::<Token>() {
+            Outcome::Success(token) => access_token::is_access_token(&token.0),
+            _ => false
+        };
+
+        Outcome::Success(ViaAccessToken(via_token))
+    }
+}
+
+// Sharing and public visibility are only open to accounts with a confirmed email address
+pub fn ensure_verified (user: UserId, db: &DefaultConnection) -> EmptyResponse {
+    let user = User::query_by_id(*user, db)
+        .to_status()?;
+
+    if !user.email_verified {
+        return Err(Status::Forbidden)
+    }
+
+    SUCCESS
+}
+
+pub struct VerifiedUser (pub UserId);
+
+impl<'a, 'r> FromRequest<'a, 'r> for VerifiedUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let user = request.guard::<UserId>()?;
+
+        DBConnection::with_request(request, |db| match ensure_verified(user, db) {
+            Ok(()) => Outcome::Success(VerifiedUser(user)),
+            Err(status) => Outcome::Failure((status, ()))
+        })
+    }
+}
+
+pub struct Authorized<P: Permission> (pub UserId, PhantomData<P>);
+
+impl<'a, 'r, P: Permission> FromRequest<'a, 'r> for Authorized<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        let user = request.guard::<UserId>()?;
+
+        DBConnection::with_request(request, |db| match has_permission(user, P::NAME, db) {
+            Ok(true) => Outcome::Success(Authorized(user, PhantomData)),
+            Ok(false) => Outcome::Failure((Status::Forbidden, ())),
+            Err(e) => Outcome::Failure((e.to_status(), ()))
+        })
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientInfo {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<Self, ()> {
+        Outcome::Success(ClientInfo {
+            ip: client_ip(request).map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(ClientInfo::user_agent_of),
+        })
     }
 }
 
@@ -0,0 +1,133 @@
+use chrono::Utc;
+use diesel::Connection;
+use rocket::{State, http::Status, response::content::Html};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, DefaultConnection, Register, Update};
+use crate::db::session::Session;
+use crate::db::access_token::AccessToken;
+use crate::db::token::{NewPasswordReset, PasswordReset};
+use crate::db::user::{Password, UpdateUser, User, UserId};
+use crate::derive_password;
+use crate::mail::Mailer;
+use crate::verification::{fingerprint, random_token};
+use super::{Catch, EmptyResponse, SUCCESS, StatusCatch, ToStatus};
+
+pub const RESET_TOKEN_MINUTES: i64 = 30;
+
+// What the emailed link opens: the token stays in the page and is posted along with the new password
+const RESET_FORM: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Reset your Contactive password</title></head>
+<body>
+<form id="reset">
+    <label>New password <input type="password" id="password" required></label>
+    <button type="submit">Reset password</button>
+</form>
+<p id="result"></p>
+<script>
+document.getElementById("reset").addEventListener("submit", function (event) {
+    event.preventDefault();
+    fetch("/password/reset", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+            token: new URLSearchParams(window.location.search).get("token") || "",
+            password: document.getElementById("password").value
+        })
+    }).then(function (response) {
+        document.getElementById("result").textContent = response.ok
+            ? "Your password has been reset, you can sign in with it now."
+            : "This link has expired or was already used.";
+    });
+});
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ForgotPassword {
+    pub email: String
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetPassword {
+    pub token: String,
+    pub password: String
+}
+
+derive_password! (ResetPassword);
+
+pub fn send_password_reset (user: &User, mailer: &Mailer, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+    let secret = random_token(32);
+
+    NewPasswordReset {
+        token_hash: fingerprint(&secret),
+        user_id: user.id,
+        expires: Utc::now().naive_utc() + chrono::Duration::minutes(RESET_TOKEN_MINUTES)
+    }.register(db)?;
+
+    mailer.enqueue(&user.email, "Reset your Contactive password", format!(
+        "Hello {},\n\nSomebody asked to reset the password of your Contactive account.\n\
+        If it was you, follow the link below within {} minutes:\n\n{}\n\n\
+        If it wasn't, you can safely ignore this mail.\n",
+        user.username,
+        RESET_TOKEN_MINUTES,
+        mailer.link(&format!("/password/reset?token={}", secret))
+    ), db)?;
+
+    Ok(())
+}
+
+#[post("/password/forgot", format = "application/json", data = "<forgot>")]
+pub fn forgot_password (forgot: Json<ForgotPassword>, db: DBConnection, mailer: State<Mailer>) -> EmptyResponse {
+    let user = match User::query_by_email(&forgot.email, &db) {
+        Ok(user) => user,
+        // Unknown addresses must look exactly like known ones from the outside
+        Err(diesel::result::Error::NotFound) => return SUCCESS,
+        Err(e) => return Err(e.to_status())
+    };
+
+    send_password_reset(&user, &mailer, &db)
+        .to_status()?;
+
+    SUCCESS
+}
+
+#[get("/password/reset")]
+pub fn reset_password_form () -> Html<&'static str> {
+    Html(RESET_FORM)
+}
+
+#[post("/password/reset", format = "application/json", data = "<reset>")]
+pub fn reset_password (reset: Json<ResetPassword>, db: DBConnection) -> EmptyResponse {
+    let reset = reset.into_inner();
+    let token_hash = fingerprint(&reset.token);
+
+    let password = reset
+        .encrypt()
+        .salt()
+        .catch(Status::InternalServerError)?
+        .password;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let user_id = PasswordReset::consume(&token_hash, &db)?;
+
+        UpdateUser {
+            password: Some(password),
+            ..Default::default()
+        }.update(&db, user_id)?;
+
+        PasswordReset::invalidate_for(user_id, &db)?;
+        Session::end_all(UserId::new(user_id), &db)?;
+        AccessToken::revoke_all(UserId::new(user_id), &db)?;
+        Ok(())
+    }).map_err(|e| match e {
+        diesel::result::Error::NotFound => Status::Unauthorized,
+        e => e.to_status()
+    })?;
+
+    SUCCESS
+}
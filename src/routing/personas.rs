@@ -0,0 +1,363 @@
+use chrono::NaiveDateTime;
+use diesel::Connection;
+use jwt_simple::prelude::Duration;
+use rocket::{State, http::Status};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, QueryById, Register};
+use crate::db::audit::{NewAuditEntry, summary};
+use crate::db::contact::{NewContact, Visibility, info::{BareInfo, Info}};
+use crate::db::persona::{NewPersona, NewPersonaToken, Persona, PersonaCopy, PersonaToken, UpdatePersona};
+use crate::db::user::{ForUser, User, UserId};
+use crate::verification::jwt::persona_jwt::{ContactJwtHandler, PERSONA_TOKEN_DEFAULT_HOURS, PersonaJwt};
+use super::contacts::contact_summary;
+use super::{EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson, ToStatus, VerifiedUser};
+
+pub const PERSONA_TOKEN_MAX_HOURS: u64 = 24 * 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PostPersona {
+    pub label: String,
+    pub name: String,
+    pub icon: Option<Vec<u8>>,
+    pub private: Option<bool>,
+    pub info: Option<BareInfo>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PatchPersona {
+    #[serde(flatten)]
+    pub changes: UpdatePersona,
+    // Replaces all of the persona's fields when present
+    pub info: Option<BareInfo>,
+}
+
+#[derive(Serialize)]
+pub struct PersonaCard {
+    #[serde(flatten)]
+    pub persona: Persona,
+    pub info: BareInfo,
+}
+
+impl PersonaCard {
+    fn of (persona: Persona, db: &crate::db::DefaultConnection) -> Result<PersonaCard, diesel::result::Error> {
+        let info = persona.info(db)?;
+        Ok(PersonaCard {
+            persona,
+            info
+        })
+    }
+}
+
+// What a recipient of a persona token gets to see
+#[derive(Serialize)]
+pub struct SharedPersona {
+    pub username: String,
+    pub name: String,
+    pub icon: Option<Vec<u8>>,
+    pub info: BareInfo,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CreatePersonaToken {
+    pub expires_in_hours: Option<u64>,
+    // Username or email of the only user allowed to open the token
+    pub recipient: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CreatedPersonaToken {
+    #[serde(flatten)]
+    pub info: PersonaToken,
+    // Only ever shown here, it can't be recovered from the token's row
+    pub token: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PersonaTokenBody {
+    pub token: String,
+}
+
+fn persona_summary (card: &PersonaCard) -> Option<String> {
+    summary(&serde_json::json!({
+        "label": card.persona.label,
+        "name": card.persona.name,
+        "private": card.persona.private,
+        "icon": card.persona.icon.is_some(),
+        "info": card.info
+    }))
+}
+
+#[get("/me/personas")]
+pub fn get_personas (user: UserId, db: DBConnection) -> JsonResponse {
+    Persona::of_user(user, &db)
+        .to_status()?
+        .into_iter()
+        .map(|persona| PersonaCard::of(persona, &db))
+        .collect::<Result<Vec<PersonaCard>, diesel::result::Error>>()
+        .to_status()?
+        .to_json()
+}
+
+#[get("/me/personas/<id>")]
+pub fn get_persona (id: i64, user: UserId, db: DBConnection) -> JsonResponse {
+    let persona = Persona::query_for(user, id, &db)
+        .to_status()?;
+
+    PersonaCard::of(persona, &db)
+        .to_status()?
+        .to_json()
+}
+
+#[post("/me/personas", format = "application/json", data = "<persona>")]
+pub fn add_persona (persona: Json<PostPersona>, user: UserId, db: DBConnection) -> JsonResponse {
+    let persona = persona.into_inner();
+
+    if persona.label.trim().is_empty() || persona.name.trim().is_empty() {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    // A label already in use, whatever its case, ends up as a 422
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let created = NewPersona {
+            private: persona.private.unwrap_or(true),
+            user_id: *user,
+            label: persona.label.trim().to_string(),
+            name: persona.name.trim().to_string(),
+            icon: persona.icon,
+        }.register(&db)?;
+
+        let info = created.replace_info(&persona.info.unwrap_or_default(), &db)?;
+        let card = PersonaCard {
+            persona: created,
+            info
+        };
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.create".to_string(),
+            target_user_id: Some(*user),
+            details: Some(card.persona.id.to_string()),
+            after_state: persona_summary(&card),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(card)
+    })
+        .to_status()?
+        .to_json()
+}
+
+#[patch("/me/personas/<id>", format = "application/json", data = "<patch>")]
+pub fn edit_persona (id: i64, patch: Json<PatchPersona>, user: UserId, db: DBConnection) -> JsonResponse {
+    let patch = patch.into_inner();
+    let mut changes = patch.changes;
+    changes.label = changes.label.map(|label| label.trim().to_string());
+    changes.name = changes.name.map(|name| name.trim().to_string());
+
+    if changes.label.as_ref().map(|label| label.is_empty()).unwrap_or(false)
+        || changes.name.as_ref().map(|name| name.is_empty()).unwrap_or(false) {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = PersonaCard::of(Persona::query_for(user, id, &db)?, &db)?;
+        let persona = Persona::update(user, id, &changes, &db)?;
+
+        let info = match &patch.info {
+            Some(info) => persona.replace_info(info, &db)?,
+            None => persona.info(&db)?
+        };
+        let after = PersonaCard {
+            persona,
+            info
+        };
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.update".to_string(),
+            target_user_id: Some(*user),
+            details: Some(id.to_string()),
+            before_state: persona_summary(&before),
+            after_state: persona_summary(&after),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(after)
+    })
+        .to_status()?
+        .to_json()
+}
+
+#[delete("/me/personas/<id>")]
+pub fn delete_persona (id: i64, user: UserId, db: DBConnection) -> EmptyResponse {
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let before = PersonaCard::of(Persona::query_for(user, id, &db)?, &db)?;
+        Persona::delete(user, id, &db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.delete".to_string(),
+            target_user_id: Some(*user),
+            details: Some(id.to_string()),
+            before_state: persona_summary(&before),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+#[post("/me/personas/<id>/token", format = "application/json", data = "<create>")]
+pub fn create_persona_token (id: i64, create: Json<CreatePersonaToken>, tokens: State<ContactJwtHandler>, user: VerifiedUser, db: DBConnection) -> JsonResponse {
+    let create = create.into_inner();
+    let user = user.0;
+    let hours = create.expires_in_hours.unwrap_or(PERSONA_TOKEN_DEFAULT_HOURS);
+
+    if hours < 1 || hours > PERSONA_TOKEN_MAX_HOURS {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    let persona = Persona::query_for(user, id, &db)
+        .to_status()?;
+
+    let recipient_id = match &create.recipient {
+        Some(login) => match User::query_by_login(login.trim(), &db) {
+            Ok(recipient) => Some(recipient.id),
+            Err(diesel::result::Error::NotFound) => return Err(Status::UnprocessableEntity),
+            Err(e) => return Err(e.to_status())
+        },
+        None => None
+    };
+
+    // Private personas are only handed to somebody in particular
+    if persona.private && recipient_id.is_none() {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    let (token, claims) = tokens.mint_persona(PersonaJwt {
+            persona_id: persona.id,
+            recipient_id
+        }, Duration::from_hours(hours))
+        .to_status()?;
+    let expires = claims.expires_at
+        .ok_or(Status::InternalServerError)?;
+
+    let info = db.transaction::<_, diesel::result::Error, _>(|| {
+        let info = NewPersonaToken {
+            id: claims.jwt_id.clone().unwrap_or_default(),
+            persona_id: persona.id,
+            recipient_id,
+            expires: NaiveDateTime::from_timestamp(expires.as_secs() as i64, 0),
+        }.register(&db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.share".to_string(),
+            target_user_id: recipient_id,
+            details: Some(info.id.clone()),
+            after_state: summary(&info),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(info)
+    }).to_status()?;
+
+    CreatedPersonaToken {
+        info,
+        token
+    }.to_json()
+}
+
+#[get("/me/personas/<id>/tokens")]
+pub fn get_persona_tokens (id: i64, user: UserId, db: DBConnection) -> JsonResponse {
+    PersonaToken::outstanding(user, id, &db)
+        .to_status()?
+        .to_json()
+}
+
+#[delete("/me/persona-tokens/<id>")]
+pub fn revoke_persona_token (id: String, user: UserId, db: DBConnection) -> EmptyResponse {
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        let revoked = PersonaToken::revoke(user, &id, &db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.revoke".to_string(),
+            target_user_id: revoked.recipient_id,
+            details: Some(id.clone()),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+fn shared_persona (persona: Persona, db: &crate::db::DefaultConnection) -> Result<SharedPersona, diesel::result::Error> {
+    let owner = User::query_by_id(persona.user_id, db)?;
+    let info = persona.info(db)?;
+
+    Ok(SharedPersona {
+        username: owner.username,
+        name: persona.name,
+        icon: persona.icon,
+        info
+    })
+}
+
+// Tokens travel in the body, paths end up in access logs
+#[post("/personas/open", format = "application/json", data = "<token>")]
+pub fn open_persona (token: Json<PersonaTokenBody>, tokens: State<ContactJwtHandler>, user: UserId, db: DBConnection) -> JsonResponse {
+    let persona = tokens.open_persona(&token.token, user, &db)
+        .to_status()?;
+
+    shared_persona(persona, &db)
+        .to_status()?
+        .to_json()
+}
+
+// The recipient keeps a private copy of the card as it was when first accepted
+#[post("/personas/accept", format = "application/json", data = "<token>")]
+pub fn accept_persona (token: Json<PersonaTokenBody>, tokens: State<ContactJwtHandler>, user: UserId, db: DBConnection) -> JsonResponse {
+    let persona = tokens.open_persona(&token.token, user, &db)
+        .to_status()?;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        if let Some(contact) = PersonaCopy::contact_of(user, persona.id, &db)? {
+            return Ok(contact)
+        }
+
+        let persona_id = persona.id;
+        let shared = shared_persona(persona, &db)?;
+        let contact = ForUser::<NewContact>::from(user)
+            .new(shared.name, shared.icon, Visibility::Private)
+            .register(&db)?;
+
+        Info {
+            contact_id: contact.id,
+            info: shared.info
+        }.register(&db)?;
+
+        PersonaCopy {
+            user_id: *user,
+            persona_id,
+            contact_id: contact.id
+        }.register(&db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "persona.accept".to_string(),
+            target_contact_id: Some(contact.id),
+            details: Some(persona_id.to_string()),
+            after_state: contact_summary(&contact),
+            ..Default::default()
+        }.register(&db)?;
+
+        Ok(contact)
+    })
+        .to_status()?
+        .to_json()
+}
@@ -0,0 +1,38 @@
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::DBConnection;
+use crate::db::role::Role;
+use crate::verification::permission::{AssignRoles, ReadRoles};
+use super::{Authorized, EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AssignRole {
+    pub role: String
+}
+
+#[get("/roles")]
+pub fn get_roles (_auth: Authorized<ReadRoles>, db: DBConnection) -> JsonResponse {
+    Role::all(&db)
+        .to_status()?
+        .to_json()
+}
+
+#[put("/users/<id>/role", format = "application/json", data = "<assign>")]
+pub fn assign_role (id: i64, assign: Json<AssignRole>, auth: Authorized<AssignRoles>, db: DBConnection) -> EmptyResponse {
+    // Otherwise the last admin could lock everybody out of role management
+    if *auth.0 == id {
+        return Err(Status::Forbidden)
+    }
+
+    if !Role::exists(&assign.role, &db).to_status()? {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    if Role::assign(id, &assign.role, &db).to_status()? == 0 {
+        return Err(Status::NotFound)
+    }
+
+    SUCCESS
+}
@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+use crate::db::DBConnection;
+use crate::db::session::Session;
+use super::{CurrentSession, EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson};
+
+#[derive(Serialize)]
+pub struct SessionView {
+    #[serde(flatten)]
+    session: Session,
+    current: bool,
+}
+
+#[get("/sessions")]
+pub fn get_sessions (current: CurrentSession, db: DBConnection) -> JsonResponse {
+    Session::of_user(current.user, &db)
+        .to_status()?
+        .into_iter()
+        .map(|session| SessionView {
+            current: session.id == current.sid,
+            session
+        })
+        .collect::<Vec<SessionView>>()
+        .to_json()
+}
+
+#[delete("/sessions/<id>")]
+pub fn end_session (id: String, current: CurrentSession, db: DBConnection) -> EmptyResponse {
+    Session::end(current.user, &id, &db)
+        .to_status()?;
+
+    SUCCESS
+}
+
+#[delete("/sessions")]
+pub fn end_other_sessions (current: CurrentSession, db: DBConnection) -> EmptyResponse {
+    Session::end_all_except(current.user, &current.sid, &db)
+        .to_status()?;
+
+    SUCCESS
+}
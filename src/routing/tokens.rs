@@ -0,0 +1,69 @@
+use chrono::Utc;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DBConnection, Register};
+use crate::db::access_token::{AccessToken, NewAccessToken};
+use crate::verification::{access_token::{SCOPES, generate}, fingerprint};
+use super::{CurrentSession, EmptyResponse, JsonResponse, SUCCESS, StatusCatch, ToJson};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CreateAccessToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct CreatedAccessToken {
+    #[serde(flatten)]
+    pub info: AccessToken,
+    // Only ever shown here, the database keeps a fingerprint
+    pub token: String,
+}
+
+// Managing tokens takes a real login, so a leaked token can't mint more of itself
+#[get("/me/tokens")]
+pub fn get_tokens (current: CurrentSession, db: DBConnection) -> JsonResponse {
+    AccessToken::of_user(current.user, &db)
+        .to_status()?
+        .to_json()
+}
+
+#[post("/me/tokens", format = "application/json", data = "<create>")]
+pub fn create_token (create: Json<CreateAccessToken>, current: CurrentSession, db: DBConnection) -> JsonResponse {
+    let create = create.into_inner();
+
+    if create.name.trim().is_empty()
+        || create.scopes.is_empty()
+        || create.scopes.iter().any(|scope| !SCOPES.contains(&&scope[..]))
+        || create.expires_in_days.map(|days| days < 1).unwrap_or(false) {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    let token = generate();
+
+    let info = NewAccessToken {
+        user_id: *current.user,
+        name: create.name.trim().to_string(),
+        token_hash: fingerprint(&token),
+        scopes: create.scopes,
+        expires: create.expires_in_days
+            .map(|days| Utc::now().naive_utc() + chrono::Duration::days(days))
+    }.register(&db)
+        .to_status()?;
+
+    CreatedAccessToken {
+        info,
+        token
+    }.to_json()
+}
+
+#[delete("/me/tokens/<id>")]
+pub fn revoke_token (id: i64, current: CurrentSession, db: DBConnection) -> EmptyResponse {
+    AccessToken::revoke(current.user, id, &db)
+        .to_status()?;
+
+    SUCCESS
+}
@@ -1,4 +1,4 @@
-use crate::{db::{DBState, Register, user::{IsUser, NewUser, Password, User}}, derive_password, verification::jwt::{JwtHandler, LoginHandler, jwt_data::JwtData}};
+use crate::{db::{DBConnection, Register, user::{IsUser, NewUser, Password, User}}, derive_password, verification::jwt::{JwtHandler, LoginHandler, jwt_data::JwtData}};
 use crate::rocket::State;
 use rocket::http::Status;
 use rocket_contrib::json::Json;
@@ -7,9 +7,27 @@ use super::{Catch, SUCCESS, StatusCatch, ToStatus};
 use crate::verification::{*, jwt::Token};
 
 use super::EmptyResponse;
-use crate::routing::{JsonResponse, ToJson};
+use crate::routing::{JsonResponse, LoginResponse, Rejection, ToJson};
+use crate::verification::throttle::Throttle;
+use super::logins::{record_failed_login, record_login};
 use crate::db::QueryById;
 use crate::db::user::UserId;
+use crate::db::session::Session;
+use crate::db::access_token::AccessToken;
+use crate::mail::Mailer;
+use crate::db::mfa::UserMfa;
+use crate::db::role::has_permission;
+use crate::verification::permission::{DeleteUsers, Permission};
+use super::email::send_email_verification;
+use super::CurrentSession;
+use crate::db::Update;
+use crate::db::user::UpdateUser;
+use crate::db::token::PasswordReset;
+use diesel::Connection;
+use crate::db::audit::{NewAuditEntry, summary};
+use rocket::http::Cookies;
+use crate::verification::cookie;
+use super::{CsrfProtected, token_response};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct RegisterUser {
@@ -25,47 +43,79 @@ impl From<&RegisterUser> for NewUser {
     }
 }
 
-#[post("/register", format = "application/json", data = "<user>")]
-pub fn register (user: Json<RegisterUser>, db: State<DBState>, jwt_key: State<LoginHandler>) -> JsonResponse {
+#[post("/register?<cookie>", format = "application/json", data = "<user>")]
+pub fn register (user: Json<RegisterUser>, cookie: Option<bool>, client: ClientInfo, db: DBConnection, jwt_key: State<LoginHandler>, mailer: State<Mailer>, throttle: State<Throttle>, cookies: Cookies) -> LoginResponse {
     let user = NewUser::from(&*user);
+    if !User::valid_username(&user.username) {
+        return Err(Status::UnprocessableEntity.into())
+    }
+
     let login_data = Login {
         username: user.username.clone(),
         password: user.password.clone()
     };
 
-    match User::query_by_username(&user.username, &db) {
-        Ok(_) => return Err(Status::UnprocessableEntity),
-        Err(e) => if e != diesel::result::Error::NotFound {
-            return Err(e.to_status());
-        }
-    }
-
     let user = user
         .encrypt()
         .salt()
         .catch(Status::InternalServerError)?;
     
-    user.register(&db)
+    // Taken usernames and emails are caught by the unique indexes, whatever their case
+    let user = user.register(&db)
+        .to_status()?;
+
+    NewAuditEntry {
+        actor_id: Some(user.id),
+        action: "user.register".to_string(),
+        target_user_id: Some(user.id),
+        after_state: summary(&Me::from(user.clone())),
+        ..Default::default()
+    }.register(&db)
+        .to_status()?;
+
+    send_email_verification(&user, &mailer, &db)
         .to_status()?;
 
-    login(Json(login_data), db, jwt_key)
+    login(Json(login_data), cookie, client, db, jwt_key, throttle, mailer, cookies)
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Login {
+    // Username or email
     pub username: String,
     pub password: String
 }
 
 derive_password! (Login);
 
-#[post("/login", format = "application/json", data = "<user>")]
-pub fn login (user: Json<Login>, db: State<DBState>, jwt_key: State<LoginHandler>) -> JsonResponse {
+#[derive(Serialize)]
+pub struct MfaRequired {
+    pub mfa_required: bool,
+    pub challenge: String
+}
+
+// With `?cookie=true` the session is kept in cookies for the web frontend
+#[post("/login?<cookie>", format = "application/json", data = "<user>")]
+pub fn login (user: Json<Login>, cookie: Option<bool>, client: ClientInfo, db: DBConnection, jwt_key: State<LoginHandler>, throttle: State<Throttle>, mailer: State<Mailer>, cookies: Cookies) -> LoginResponse {
 
     println! ("\t=> Logging in {}", user.username);
 
-    let dbuser = User::query_by_username(&user.username, &db)
-        .to_status()?;
+    throttle.check_address(&client.ip)
+        .map_err(Rejection::RetryAfter)?;
+
+    let dbuser = match User::query_by_login(&user.username, &db) {
+        Ok(dbuser) => dbuser,
+        Err(e) => {
+            throttle.address_failed(&client.ip);
+            return Err(e.to_status().into())
+        }
+    };
+
+    if let Err(secs) = throttle.check_account(&dbuser) {
+        record_failed_login(&dbuser, &client, "locked", &db)
+            .to_status()?;
+        return Err(Rejection::RetryAfter(secs))
+    }
 
     let user = user.encrypt ();
 
@@ -73,20 +123,53 @@ pub fn login (user: Json<Login>, db: State<DBState>, jwt_key: State<LoginHandler
         .catch(Status::InternalServerError)?;
 
     if !authorized {
-        return Err(Status::Unauthorized)
+        throttle.address_failed(&client.ip);
+        throttle.account_failed(&dbuser, &db)
+            .to_status()?;
+        record_failed_login(&dbuser, &client, "password", &db)
+            .to_status()?;
+        return Err(Status::Unauthorized.into())
     }
 
     println! ("\t=> Password is correct");
 
-    let mut out: String = "".to_string();
-    jwt_key.authorize(&mut out, dbuser)
-        .catch(Status::InternalServerError)?;
+    // The plaintext is only ever at hand here, so outdated hashes are upgraded on the way in
+    if dbuser.needs_rehash() {
+        let upgraded = user.salt()
+            .catch(Status::InternalServerError)
+            .and_then(|user| UpdateUser {
+                    password: Some(user.password),
+                    ..Default::default()
+                }.update(&db, dbuser.id)
+                .to_status());
+
+        if upgraded.is_err() {
+            println! ("\t=> Could not upgrade the password hash of {}", dbuser.username);
+        }
+    }
+
+    // With a second factor the failure count is only cleared once the code is right as well
+    if UserMfa::is_enabled(dbuser.id, &db).to_status()? {
+        return Ok(MfaRequired {
+            mfa_required: true,
+            challenge: jwt_key.challenge(dbuser.id)
+                .to_status()?
+        }.to_json()?)
+    }
 
-    out.to_json()
+    throttle.account_succeeded(&dbuser, &db)
+        .to_status()?;
+    record_login(&dbuser, &client, &mailer, &db)
+        .to_status()?;
+
+    let pair = jwt_key.issue(dbuser, &client, &db)
+        .to_status()?;
+
+    Ok(token_response(pair, cookie, cookies, &jwt_key)?)
 }
 
 #[post("/logout")]
-pub fn logout (jwt_key: State<LoginHandler>, token: Token) -> EmptyResponse {
+pub fn logout (jwt_key: State<LoginHandler>, db: DBConnection, token: Token, mut cookies: Cookies) -> EmptyResponse {
 
     let auth = token.0;
 
@@ -95,17 +178,31 @@ pub fn logout (jwt_key: State<LoginHandler>, token: Token) -> EmptyResponse {
 
     println!("\t=> Logging out {}", jwt.custom.username);
 
-    (&*jwt_key).blacklist(JwtData::new_from_claims (jwt, auth));
+    Session::revoke(&jwt.custom.sid, &db)
+        .to_status()?;
+
+    NewAuditEntry {
+        actor_id: Some(jwt.custom.user_id),
+        action: "user.logout".to_string(),
+        target_user_id: Some(jwt.custom.user_id),
+        details: Some(jwt.custom.sid.clone()),
+        ..Default::default()
+    }.register(&db)
+        .to_status()?;
+
+    (&*jwt_key).blacklist(JwtData::new_from_claims (jwt, auth), &db)
+        .to_status()?;
+    cookie::end_session(&mut cookies);
 
     SUCCESS
 }
 
 #[delete("/", format = "application/json", data = "<login>")]
-pub fn delete (login: Json<Login>, db: State<DBState>, user: UserId) -> EmptyResponse {
-    let dbuser = User::query_by_username(&login.username, &**db)
+pub fn delete (login: Json<Login>, user: UserId, db: DBConnection) -> EmptyResponse {
+    let dbuser = User::query_by_login(&login.username, &db)
         .to_status()?;
 
-    if dbuser.id != *user && dbuser.level < 1 {
+    if dbuser.id != *user && !has_permission(user, DeleteUsers::NAME, &db).to_status()? {
         return Err(Status::Unauthorized)
     }
 
@@ -118,9 +215,18 @@ pub fn delete (login: Json<Login>, db: State<DBState>, user: UserId) -> EmptyRes
         return Err(Status::Unauthorized)
     }
 
-    dbuser
-        .delete(&db)
-        .to_status()?;
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        dbuser.delete(&db)?;
+
+        NewAuditEntry {
+            actor_id: Some(*user),
+            action: "user.delete".to_string(),
+            target_user_id: Some(dbuser.id),
+            before_state: summary(&Me::from(dbuser.clone())),
+            ..Default::default()
+        }.register(&db)?;
+        Ok(())
+    }).to_status()?;
 
     Ok(())
 }
@@ -129,6 +235,7 @@ pub fn delete (login: Json<Login>, db: State<DBState>, user: UserId) -> EmptyRes
 pub struct Me {
     username: String,
     email: String,
+    email_verified: bool,
     id: i64,
 }
 
@@ -137,22 +244,156 @@ impl From<User> for Me {
         Me {
             username: u.username,
             email: u.email,
+            email_verified: u.email_verified,
             id: u.id
         }
     }
 }
 
 #[get("/me")]
-pub fn me (db: State<DBState>, user: UserId) -> JsonResponse {
+pub fn me (user: UserId, db: DBConnection) -> JsonResponse {
     println!("Me! token = {}", *user);
 
-    Me::from(User::query_by_id(*user, &**db)
+    Me::from(User::query_by_id(*user, &db)
         .catch(Status::NotFound)?).to_json()
 }
 
-#[post("/renew")]
-pub fn renew (jwt_key: State<LoginHandler>, token: Token) -> JsonResponse {
-    let mut out: String = "".to_string();
-    jwt_key.reauthorize(&token, &mut out).map_err(|_| Status::Unauthorized)?;
-    out.to_json()
-}
\ No newline at end of file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EditMe {
+    pub username: Option<String>,
+    pub email: Option<String>
+}
+
+// Anybody else already holding the name or address makes the change fail
+fn taken_by_other (found: Result<User, diesel::result::Error>, user: UserId) -> Result<bool, Status> {
+    match found {
+        Ok(other) => Ok(other.id != *user),
+        Err(diesel::result::Error::NotFound) => Ok(false),
+        Err(e) => Err(e.to_status())
+    }
+}
+
+#[patch("/me", format = "application/json", data = "<edit>")]
+pub fn edit_me (edit: Json<EditMe>, mailer: State<Mailer>, current: CurrentSession, db: DBConnection) -> JsonResponse {
+    let edit = edit.into_inner();
+    let dbuser = User::query_by_id(*current.user, &db)
+        .to_status()?;
+
+    let username = edit.username
+        .map(|username| username.trim().to_string())
+        .filter(|username| *username != dbuser.username);
+    // Addresses are matched without case everywhere else, so a change of case alone isn't a new address
+    let email = edit.email
+        .map(|email| email.trim().to_string())
+        .filter(|email| !email.eq_ignore_ascii_case(&dbuser.email));
+
+    if username.as_ref().map(|u| !User::valid_username(u)).unwrap_or(false)
+        || email.as_ref().map(|e| e.is_empty()).unwrap_or(false) {
+        return Err(Status::UnprocessableEntity)
+    }
+
+    if username.is_none() && email.is_none() {
+        return Me::from(dbuser).to_json()
+    }
+
+    if let Some(username) = &username {
+        if taken_by_other(User::query_by_username(username, &db), current.user)? {
+            return Err(Status::UnprocessableEntity)
+        }
+    }
+
+    if let Some(email) = &email {
+        if taken_by_other(User::query_by_email(email, &db), current.user)? {
+            return Err(Status::UnprocessableEntity)
+        }
+    }
+
+    let email_changed = email.is_some();
+
+    let user = db.transaction::<_, diesel::result::Error, _>(|| {
+        let user = UpdateUser {
+            username,
+            // A new address has to be verified again
+            email_verified: email.as_ref().map(|_| false),
+            email,
+            ..Default::default()
+        }.update(&db, *current.user)?;
+
+        if email_changed {
+            send_email_verification(&user, &mailer, &db)?;
+        }
+
+        Ok(user)
+    }).to_status()?;
+
+    Me::from(user).to_json()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChangePassword {
+    // The current password
+    pub password: String,
+    pub new_password: String
+}
+
+derive_password! (ChangePassword);
+
+#[post("/me/password", format = "application/json", data = "<change>")]
+pub fn change_password (change: Json<ChangePassword>, current: CurrentSession, db: DBConnection) -> EmptyResponse {
+    let change = change.into_inner();
+    let dbuser = User::query_by_id(*current.user, &db)
+        .to_status()?;
+
+    let authorized = dbuser.password_cmp(&change.encrypt())
+        .catch(Status::InternalServerError)?;
+
+    if !authorized {
+        return Err(Status::Unauthorized)
+    }
+
+    let password = ChangePassword {
+            password: change.new_password.clone(),
+            ..change
+        }
+        .encrypt()
+        .salt()
+        .catch(Status::InternalServerError)?
+        .password;
+
+    db.transaction::<_, diesel::result::Error, _>(|| {
+        UpdateUser {
+            password: Some(password),
+            ..Default::default()
+        }.update(&db, *current.user)?;
+
+        PasswordReset::invalidate_for(*current.user, &db)?;
+        Session::end_all_except(current.user, &current.sid, &db)?;
+        AccessToken::revoke_all(current.user, &db)?;
+        Ok(())
+    }).to_status()?;
+
+    SUCCESS
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Renew {
+    pub refresh_token: String
+}
+
+#[post("/renew", format = "application/json", data = "<renew>")]
+pub fn renew (renew: Json<Renew>, client: ClientInfo, db: DBConnection, jwt_key: State<LoginHandler>) -> JsonResponse {
+    jwt_key.refresh(&renew.refresh_token, &client, &db)
+        .to_status()?
+        .to_json()
+}
+// Cookie sessions renew with the refresh cookie instead of a JSON body
+#[post("/renew", rank = 2)]
+pub fn renew_cookie (client: ClientInfo, db: DBConnection, jwt_key: State<LoginHandler>, _csrf: CsrfProtected, mut cookies: Cookies) -> JsonResponse {
+    let refresh_token = cookie::refresh_token(&mut cookies)
+        .ok_or(Status::Unauthorized)?;
+
+    let pair = jwt_key.refresh(&refresh_token, &client, &db)
+        .to_status()?;
+
+    token_response(pair, Some(true), cookies, &jwt_key)
+}
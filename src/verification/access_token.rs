@@ -0,0 +1,72 @@
+use rocket::http::Status;
+
+use crate::db::{DefaultConnection, access_token::AccessToken, user::UserId};
+use crate::routing::ToStatus;
+use super::{fingerprint, random_token};
+
+// Lets the request guards tell personal access tokens apart from JWTs without decoding anything
+pub const ACCESS_TOKEN_PREFIX: &str = "ctv_";
+
+pub const SCOPES: [&str; 3] = ["contacts:read", "contacts:write", "info:write"];
+
+// Routes missing from here can't be called with a personal access token at all
+pub fn required_scope (route: &str) -> Option<&'static str> {
+    match route {
+        "get_contacts" | "get_info" | "search_directory" | "get_directory_entry" => Some("contacts:read"),
+        "add_contacts" | "delete_contact" | "edit_contact" | "save_from_directory" => Some("contacts:write"),
+        "post_info_by_data" | "post_info_by_url" | "delete_info" | "patch_info" => Some("info:write"),
+        _ => None
+    }
+}
+
+pub fn is_access_token (token: &str) -> bool {
+    token.starts_with (ACCESS_TOKEN_PREFIX)
+}
+
+pub fn generate () -> String {
+    format! ("{}{}", ACCESS_TOKEN_PREFIX, random_token (32))
+}
+
+#[derive(Debug)]
+pub enum AccessTokenError {
+    Invalid,
+    OutOfScope,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for AccessTokenError {
+    fn from(e: diesel::result::Error) -> Self {
+        AccessTokenError::Database(e)
+    }
+}
+
+impl ToStatus for AccessTokenError {
+    fn to_status (&self) -> Status {
+        match self {
+            AccessTokenError::Invalid => Status::Unauthorized,
+            AccessTokenError::OutOfScope => Status::Forbidden,
+            AccessTokenError::Database(e) => e.to_status(),
+        }
+    }
+}
+
+pub fn authenticate (secret: &str, route: Option<&str>, db: &DefaultConnection) -> Result<UserId, AccessTokenError> {
+    let token = match AccessToken::query_by_hash (&fingerprint (secret), db) {
+        Ok(token) => token,
+        Err(diesel::result::Error::NotFound) => return Err(AccessTokenError::Invalid),
+        Err(e) => return Err(e.into ())
+    };
+
+    if !token.is_valid () {
+        return Err(AccessTokenError::Invalid)
+    }
+
+    match route.and_then (required_scope) {
+        Some(scope) if token.has_scope (scope) => {},
+        _ => return Err(AccessTokenError::OutOfScope)
+    }
+
+    AccessToken::touch (token.id, db)?;
+
+    Ok(UserId::new (token.user_id))
+}
@@ -0,0 +1,143 @@
+use rocket::Request;
+use rocket::http::{Cookie, Cookies, Method, SameSite};
+use serde::Serialize;
+use time::Duration;
+
+use super::jwt::ACCESS_TOKEN_MINUTES;
+use super::jwt::refresh::{REFRESH_TOKEN_DAYS, TokenPair};
+use super::random_token;
+
+pub const SESSION_COOKIE: &str = "contactive_session";
+pub const REFRESH_COOKIE: &str = "contactive_refresh";
+// The private copy is what requests are checked against, the readable one is for the frontend to echo back
+pub const CSRF_COOKIE: &str = "contactive_csrf";
+pub const CSRF_READABLE_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+// The refresh token is only ever sent along to renew the session
+const REFRESH_PATH: &str = "/renew";
+
+#[derive(Serialize)]
+pub struct CookieSession {
+    pub expires_in: u64,
+    pub csrf_token: String,
+}
+
+fn private_cookie (name: &'static str, value: String, path: &'static str, max_age: Duration, secure: bool) -> Cookie<'static> {
+    Cookie::build (name, value)
+        .path (path)
+        .max_age (max_age)
+        .secure (secure)
+        .http_only (true)
+        .same_site (SameSite::Strict)
+        .finish ()
+}
+
+pub fn start_session (cookies: &mut Cookies, pair: TokenPair, secure: bool) -> CookieSession {
+    let csrf_token = random_token (32);
+    let lifetime = Duration::days (REFRESH_TOKEN_DAYS);
+
+    cookies.add_private (private_cookie (SESSION_COOKIE, pair.access_token, "/", Duration::minutes (ACCESS_TOKEN_MINUTES as i64), secure));
+    cookies.add_private (private_cookie (REFRESH_COOKIE, pair.refresh_token, REFRESH_PATH, lifetime, secure));
+    cookies.add_private (private_cookie (CSRF_COOKIE, csrf_token.clone (), "/", lifetime, secure));
+    cookies.add (Cookie::build (CSRF_READABLE_COOKIE, csrf_token.clone ())
+        .path ("/")
+        .max_age (lifetime)
+        .secure (secure)
+        .http_only (false)
+        .same_site (SameSite::Strict)
+        .finish ());
+
+    CookieSession {
+        expires_in: pair.expires_in,
+        csrf_token
+    }
+}
+
+pub fn end_session (cookies: &mut Cookies) {
+    cookies.remove_private (Cookie::named (SESSION_COOKIE));
+    cookies.remove_private (Cookie::build (REFRESH_COOKIE, "").path (REFRESH_PATH).finish ());
+    cookies.remove_private (Cookie::named (CSRF_COOKIE));
+    cookies.remove (Cookie::build (CSRF_READABLE_COOKIE, "").path ("/").finish ());
+}
+
+pub fn refresh_token (cookies: &mut Cookies) -> Option<String> {
+    cookies.get_private (REFRESH_COOKIE)
+        .map (|cookie| cookie.value ().to_string ())
+}
+
+fn constant_time_eq (a: &[u8], b: &[u8]) -> bool {
+    a.len () == b.len () && a.iter ().zip (b).fold (0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Double submit: a state-changing request has to repeat the token in a header that other sites cannot set
+pub fn csrf_passes (request: &Request, cookies: &mut Cookies) -> bool {
+    match request.method () {
+        Method::Get | Method::Head | Method::Options => return true,
+        _ => {}
+    }
+
+    match (request.headers ().get_one (CSRF_HEADER), cookies.get_private (CSRF_COOKIE)) {
+        (Some(header), Some(cookie)) => constant_time_eq (header.as_bytes (), cookie.value ().as_bytes ()),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use rocket::config::Config;
+    use rocket::http::Header;
+    use rocket::local::{Client, LocalRequest};
+
+    fn client () -> Client {
+        Client::new (rocket::custom (Config::development ())).unwrap ()
+    }
+
+    fn passes (request: LocalRequest) -> bool {
+        let request = request.inner ();
+        csrf_passes (request, &mut request.cookies ())
+    }
+
+    #[test]
+    fn safe_methods_need_no_token () {
+        let client = client ();
+
+        assert!(passes (client.get ("/")));
+        assert!(passes (client.head ("/")));
+        assert!(passes (client.options ("/")));
+    }
+
+    #[test]
+    fn header_has_to_match_the_private_cookie () {
+        let client = client ();
+
+        assert!(passes (client.post ("/")
+            .header (Header::new (CSRF_HEADER, "token"))
+            .private_cookie (Cookie::new (CSRF_COOKIE, "token"))));
+        assert!(!passes (client.post ("/")
+            .header (Header::new (CSRF_HEADER, "other"))
+            .private_cookie (Cookie::new (CSRF_COOKIE, "token"))));
+    }
+
+    #[test]
+    fn missing_header_or_cookie_fails () {
+        let client = client ();
+
+        assert!(!passes (client.delete ("/")
+            .private_cookie (Cookie::new (CSRF_COOKIE, "token"))));
+        assert!(!passes (client.patch ("/")
+            .header (Header::new (CSRF_HEADER, "token"))));
+    }
+
+    #[test]
+    fn readable_cookie_alone_is_not_enough () {
+        let client = client ();
+
+        // Only the private copy counts, the readable one can be planted by a sibling domain
+        assert!(!passes (client.put ("/")
+            .header (Header::new (CSRF_HEADER, "token"))
+            .cookie (Cookie::new (CSRF_COOKIE, "token"))));
+    }
+}
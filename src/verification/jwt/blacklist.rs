@@ -1,88 +1,106 @@
-use std::{sync::{Arc, Mutex, MutexGuard}, thread::{self, sleep}, time::{SystemTime, UNIX_EPOCH}};
+use std::{collections::{BTreeSet, HashMap}, sync::{Arc, Mutex, MutexGuard}, thread::{self, sleep}, time::{SystemTime, UNIX_EPOCH}};
 use jwt_simple::prelude::{Duration, UnixTimeStamp};
-use sorted_vec::SortedVec;
+use postgres::{Client, NoTls, fallible_iterator::FallibleIterator};
 use crate::verification::Blacklist;
+use crate::db::{DBPool, DefaultConnection, database_url, token::RevokedToken};
 use super::jwt_data::JwtData;
 use std::thread::JoinHandle;
 
+pub const REVOCATION_CHANNEL: &str = "token_revoked";
+pub const GC_INTERVAL_SECS: u64 = 10 * 60;
+
+fn now () -> UnixTimeStamp {
+    Duration::from_secs(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect ("Time went backwards")
+            .as_secs()
+    )
+}
+
+// Revocations indexed by jti for constant-time lookups, with a separate expiry ordering for garbage collection
+#[derive(Default, Debug)]
+pub struct Revocations {
+    by_id: HashMap<String, UnixTimeStamp>,
+    by_expiry: BTreeSet<JwtData>,
+}
+
+impl Revocations {
+
+    pub fn insert (&mut self, jwt: JwtData) {
+        if self.by_id.insert (jwt.jti.clone (), jwt.expires).is_none () {
+            self.by_expiry.insert (jwt);
+        }
+    }
+
+    pub fn contains (&self, id: &str) -> bool {
+        self.by_id.contains_key (id)
+    }
+
+    pub fn clear (&mut self) {
+        self.by_id.clear ();
+        self.by_expiry.clear ();
+    }
+
+    pub fn collect_garbage (&mut self, now: UnixTimeStamp) {
+        loop {
+            let expired = match self.by_expiry.iter ().next () {
+                Some(data) if data.expires < now => JwtData::new (data.expires, data.jti.clone ()),
+                _ => break
+            };
+            self.by_id.remove (&expired.jti);
+            self.by_expiry.remove (&expired);
+        }
+    }
+
+    pub fn iter (&self) -> impl Iterator<Item = &JwtData> {
+        self.by_expiry.iter ()
+    }
+
+}
+
 #[derive(Clone)]
-pub struct ThreadBlacklist(Arc<Mutex<SortedVec<JwtData>>>);
+pub struct ThreadBlacklist(Arc<Mutex<Revocations>>);
 
 impl ThreadBlacklist {
 
     pub fn new () -> Self {
-        let mut bl = Self(Arc::new(Mutex::new(SortedVec::new())));
-        bl.start_gc ();
-        bl
+        Self(Arc::new(Mutex::new(Revocations::default ())))
     }
 
-    fn lock (&self) -> MutexGuard<SortedVec<JwtData>> {
+    fn lock (&self) -> MutexGuard<Revocations> {
         match (*self.0).lock () {
             Ok(guard) => guard,
             Err(poisoned) => {
                 let mut guard = poisoned.into_inner ();
-                let _ = std::mem::replace (&mut *guard, SortedVec::new ());
+                guard.clear ();
                 guard
             }
         }
     }
 
-    fn start_gc (&mut self) -> JoinHandle<()> {
-        let this = self.clone ();
-        thread::spawn (move || {
-            loop {
-                let duration = std::time::Duration::from_secs (5 * 60);
-                sleep (duration);
-                {
-                    let mut queue = this.lock ();
-
-                    let mut to_delete: i128 = 0;
-                    let now: UnixTimeStamp = Duration::from_secs(
-                        SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .expect ("Time went backwards")
-                            .as_secs()
-                    );
-
-                    for auth in (&**queue).into_iter() {
-                        if auth.expires < now {
-                            to_delete += 1;
-                        } else {
-                            break
-                        }
-                    }
-
-                    for _ in 0..to_delete {
-                        queue.remove_index (0);
-                    }
-                }
-            }
-        })
-    }
-
 }
 
 impl Blacklist for ThreadBlacklist {
 
     type Data = JwtData;
 
-    fn blacklist (&self, jwt: JwtData) {
-        self.lock ().insert (jwt);
+    fn blacklist (&self, jwt: JwtData, _: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        let mut revoked = self.lock ();
+        revoked.collect_garbage (now ());
+        revoked.insert (jwt);
+        Ok(())
     }
 
-    fn is_blacklisted (&self, token: &String) -> bool {
-        self.lock ().iter ()
-            .filter (|data| &data.token == token)
-            .take(1)
-            .collect::<Vec<&JwtData>> ()
-            .len () > 0
+    fn is_blacklisted (&self, id: &str) -> bool {
+        self.lock ().contains (id)
     }
 
 }
 
 impl std::ops::Deref for ThreadBlacklist {
 
-    type Target = Arc<Mutex<SortedVec<JwtData>>>;
+    type Target = Arc<Mutex<Revocations>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -99,12 +117,162 @@ impl std::ops::DerefMut for ThreadBlacklist {
 impl std::fmt::Display for ThreadBlacklist {
 
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string = (&**self.lock ())
-            .into_iter ()
+        let string = self.lock ()
+            .iter ()
             .map (|data| format!("{:?}", data))
             .collect::<Vec<String>> ()
             .join (", ");
         write!(f, "Blacklist [ {} ]", string)
     }
 
-}
\ No newline at end of file
+}
+
+// Revocations are stored in postgres and mirrored in memory on every instance.
+// Inserts fire a `token_revoked` notification, which keeps the other instances' mirrors current.
+#[derive(Clone)]
+pub struct PgBlacklist {
+    pool: DBPool,
+    revoked: Arc<Mutex<Revocations>>,
+}
+
+impl PgBlacklist {
+
+    pub fn new (pool: DBPool) -> Self {
+        let mut bl = Self {
+            pool,
+            revoked: Arc::new(Mutex::new(Revocations::default ())),
+        };
+        bl.start_listener ();
+        bl.start_collector ();
+        bl
+    }
+
+    fn lock (&self) -> MutexGuard<Revocations> {
+        match (*self.revoked).lock () {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner ()
+        }
+    }
+
+    fn reload (&self) -> Result<(), Box<dyn std::error::Error>> {
+        let db = self.pool.get ()?;
+        let active = RevokedToken::active (&db)?;
+
+        let mut revoked = self.lock ();
+        revoked.clear ();
+        for token in active {
+            revoked.insert (JwtData::new (Duration::from_secs (token.expires.timestamp () as u64), token.jti));
+        }
+
+        Ok(())
+    }
+
+    fn listen (&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = Client::connect (&database_url ()[..], NoTls)?;
+        client.batch_execute (&format!("LISTEN {}", REVOCATION_CHANNEL)[..])?;
+
+        // Anything revoked while we were not listening is picked up here
+        self.reload ()?;
+
+        let mut notifications = client.notifications ();
+        let mut iter = notifications.blocking_iter ();
+        while let Some(notification) = iter.next ()? {
+            let mut payload = notification.payload ().split (' ');
+            if let (Some(jti), Some(Ok(expires))) = (payload.next (), payload.next ().map (|e| e.parse::<u64> ())) {
+                self.lock ().insert (JwtData::new (Duration::from_secs (expires), jti.to_string ()));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_garbage (&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.lock ().collect_garbage (now ());
+
+        let db = self.pool.get ()?;
+        RevokedToken::collect_garbage (&db)?;
+        Ok(())
+    }
+
+    // Expired revocations are swept on a timer rather than on every revoke
+    fn start_collector (&self) -> JoinHandle<()> {
+        let this = self.clone ();
+        thread::spawn (move || {
+            loop {
+                sleep (std::time::Duration::from_secs (GC_INTERVAL_SECS));
+                if let Err(e) = this.collect_garbage () {
+                    println!("\t=>\u{001b}[1;31m Revocation garbage collection: {:?}\u{001b}[0m", e);
+                }
+            }
+        })
+    }
+
+    fn start_listener (&mut self) -> JoinHandle<()> {
+        let this = self.clone ();
+        thread::spawn (move || {
+            loop {
+                if let Err(e) = this.listen () {
+                    println!("\t=>\u{001b}[1;31m Revocation listener: {:?}\u{001b}[0m", e);
+                }
+                sleep (std::time::Duration::from_secs (5));
+            }
+        })
+    }
+
+}
+
+impl Blacklist for PgBlacklist {
+
+    type Data = JwtData;
+
+    // Only mirrored locally once saved, the other instances hear about it from the saved row
+    fn blacklist (&self, jwt: JwtData, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        RevokedToken::from (&jwt).revoke (db)?;
+        self.lock ().insert (jwt);
+        Ok(())
+    }
+
+    fn is_blacklisted (&self, id: &str) -> bool {
+        self.lock ().contains (id)
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn revoked (expires: u64, jti: &str) -> JwtData {
+        JwtData::new (Duration::from_secs (expires), jti.to_string ())
+    }
+
+    #[test]
+    fn garbage_collection_drops_only_expired_tokens () {
+        let mut revocations = Revocations::default ();
+        revocations.insert (revoked (10, "a"));
+        revocations.insert (revoked (20, "b"));
+        revocations.insert (revoked (30, "c"));
+
+        revocations.collect_garbage (Duration::from_secs (20));
+
+        assert!(!revocations.contains ("a"));
+        assert!(revocations.contains ("b"));
+        assert!(revocations.contains ("c"));
+        assert_eq!(revocations.iter ().count (), 2);
+    }
+
+    #[test]
+    fn revoking_twice_keeps_a_single_entry () {
+        let mut revocations = Revocations::default ();
+        revocations.insert (revoked (10, "a"));
+        revocations.insert (revoked (50, "a"));
+
+        assert_eq!(revocations.iter ().count (), 1);
+
+        revocations.collect_garbage (Duration::from_secs (11));
+        assert!(!revocations.contains ("a"));
+        assert_eq!(revocations.iter ().count (), 0);
+    }
+
+}
@@ -1,38 +1,58 @@
 use std::cmp::Ordering;
 
+use chrono::NaiveDateTime;
 use jwt_simple::prelude::{Duration, JWTClaims, UnixTimeStamp};
-
+use crate::db::token::RevokedToken;
+use crate::verification::fingerprint;
 use super::Jwt;
 
 
 #[derive(Debug)]
 pub struct JwtData {
     pub expires: UnixTimeStamp,
-    pub token: String
+    pub jti: String
 }
 
 impl JwtData {
 
-    pub fn new (expires: UnixTimeStamp, token: String) -> JwtData {
+    pub fn new (expires: UnixTimeStamp, jti: String) -> JwtData {
         JwtData {
             expires,
-            token
+            jti
         }
     }
 
     pub fn new_from_claims<'a> (claims: JWTClaims<impl Jwt>, token: String) -> JwtData {
         Self::new (
             claims.expires_at.unwrap_or(Duration::from_hours(2)),
-            token
+            Self::id_of (&claims, &token)
+        )
+    }
+
+    // Tokens without a `jti` claim are identified by their fingerprint
+    pub fn id_of (claims: &JWTClaims<impl Jwt>, token: &str) -> String {
+        match &claims.jwt_id {
+            Some(jti) => jti.clone (),
+            None => fingerprint (token)
+        }
+    }
+
+}
+
+impl From<&JwtData> for RevokedToken {
+    fn from(data: &JwtData) -> Self {
+        RevokedToken::new (
+            data.jti.clone (),
+            NaiveDateTime::from_timestamp (data.expires.as_secs() as i64, 0)
         )
     }
-    
 }
 
 impl Ord for JwtData {
 
     fn cmp(&self, other: &Self) -> Ordering {
         self.expires.cmp(&other.expires)
+            .then_with(|| self.jti.cmp(&other.jti))
     }
 
 }
@@ -52,6 +72,6 @@ impl PartialOrd for JwtData {
 impl PartialEq for JwtData {
 
     fn eq(&self, other: &Self) -> bool {
-        self.expires == other.expires
+        self.expires == other.expires && self.jti == other.jti
     }
 }
@@ -0,0 +1,341 @@
+use std::{error::Error, fs, io::Write, os::unix::fs::{OpenOptionsExt, PermissionsExt}, path::{Path, PathBuf}};
+
+use jwt_simple::{prelude::{ECDSAP256KeyPairLike, ECDSAP256PublicKeyLike, ES256KeyPair, EdDSAKeyPairLike, EdDSAPublicKeyLike, Ed25519KeyPair, HS256Key, JWTClaims, MACLike, VerificationOptions}, token::Token as JwtToken};
+use rocket::config::Config;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::verification::random_token;
+use super::{Jwt, JwtKey};
+
+pub const DEFAULT_KEY_FILE: &str = "jwt_keys.json";
+pub const DEFAULT_KEY_WINDOW: usize = 2;
+pub const DEFAULT_ALGORITHM: &str = "HS256";
+
+fn default_algorithm () -> String {
+    DEFAULT_ALGORITHM.to_string()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyEntry {
+    pub kid: String,
+    #[serde(default = "default_algorithm")]
+    pub alg: String,
+    pub secret: String,
+}
+
+impl KeyEntry {
+
+    pub fn generate (alg: &str) -> Result<KeyEntry, Box<dyn Error>> {
+        let secret = match alg {
+            "HS256" => HS256Key::generate().to_bytes(),
+            "EdDSA" => Ed25519KeyPair::generate().to_bytes(),
+            "ES256" => ES256KeyPair::generate().to_bytes(),
+            _ => return Err(format!("Unsupported signing algorithm {}", alg).into())
+        };
+
+        Ok(KeyEntry {
+            kid: random_token(8),
+            alg: alg.to_string(),
+            secret: base64::encode(secret)
+        })
+    }
+
+    pub fn from_secret (kid: &str, secret: &[u8]) -> KeyEntry {
+        KeyEntry {
+            kid: kid.to_string(),
+            alg: default_algorithm(),
+            secret: base64::encode(secret)
+        }
+    }
+
+    fn key (&self) -> Result<SigningKey, Box<dyn Error>> {
+        let secret = base64::decode(&self.secret)?;
+        Ok(match &self.alg[..] {
+            "HS256" => SigningKey::HS256(HS256Key::from_bytes(&secret[..])
+                .with_key_id(&self.kid)),
+            "EdDSA" => SigningKey::EdDSA(Ed25519KeyPair::from_bytes(&secret[..])?
+                .with_key_id(&self.kid)),
+            "ES256" => SigningKey::ES256(ES256KeyPair::from_bytes(&secret[..])?
+                .with_key_id(&self.kid)),
+            alg => return Err(format!("Unsupported signing algorithm {}", alg).into())
+        })
+    }
+
+}
+
+pub enum SigningKey {
+    HS256(HS256Key),
+    EdDSA(Ed25519KeyPair),
+    ES256(ES256KeyPair),
+}
+
+impl JwtKey for HS256Key {
+
+    fn sign<C: Serialize + DeserializeOwned> (&self, claims: JWTClaims<C>) -> Result<String, jwt_simple::Error> {
+        self.authenticate(claims)
+    }
+
+    fn verify<C: Serialize + DeserializeOwned> (&self, token: &str, options: VerificationOptions) -> Result<JWTClaims<C>, jwt_simple::Error> {
+        self.verify_token::<C>(token, Some(options))
+    }
+
+    fn kid (&self) -> Option<&str> {
+        MACLike::key_id(self).as_deref()
+    }
+
+}
+
+impl JwtKey for Ed25519KeyPair {
+
+    fn sign<C: Serialize + DeserializeOwned> (&self, claims: JWTClaims<C>) -> Result<String, jwt_simple::Error> {
+        EdDSAKeyPairLike::sign(self, claims)
+    }
+
+    fn verify<C: Serialize + DeserializeOwned> (&self, token: &str, options: VerificationOptions) -> Result<JWTClaims<C>, jwt_simple::Error> {
+        self.public_key().verify_token::<C>(token, Some(options))
+    }
+
+    fn kid (&self) -> Option<&str> {
+        EdDSAKeyPairLike::key_id(self).as_deref()
+    }
+
+}
+
+impl JwtKey for ES256KeyPair {
+
+    fn sign<C: Serialize + DeserializeOwned> (&self, claims: JWTClaims<C>) -> Result<String, jwt_simple::Error> {
+        ECDSAP256KeyPairLike::sign(self, claims)
+    }
+
+    fn verify<C: Serialize + DeserializeOwned> (&self, token: &str, options: VerificationOptions) -> Result<JWTClaims<C>, jwt_simple::Error> {
+        self.public_key().verify_token::<C>(token, Some(options))
+    }
+
+    fn kid (&self) -> Option<&str> {
+        ECDSAP256KeyPairLike::key_id(self).as_deref()
+    }
+
+}
+
+impl JwtKey for SigningKey {
+
+    fn sign<C: Serialize + DeserializeOwned> (&self, claims: JWTClaims<C>) -> Result<String, jwt_simple::Error> {
+        match self {
+            SigningKey::HS256(key) => JwtKey::sign(key, claims),
+            SigningKey::EdDSA(key) => JwtKey::sign(key, claims),
+            SigningKey::ES256(key) => JwtKey::sign(key, claims),
+        }
+    }
+
+    fn verify<C: Serialize + DeserializeOwned> (&self, token: &str, options: VerificationOptions) -> Result<JWTClaims<C>, jwt_simple::Error> {
+        match self {
+            SigningKey::HS256(key) => JwtKey::verify(key, token, options),
+            SigningKey::EdDSA(key) => JwtKey::verify(key, token, options),
+            SigningKey::ES256(key) => JwtKey::verify(key, token, options),
+        }
+    }
+
+    fn kid (&self) -> Option<&str> {
+        match self {
+            SigningKey::HS256(key) => JwtKey::kid(key),
+            SigningKey::EdDSA(key) => JwtKey::kid(key),
+            SigningKey::ES256(key) => JwtKey::kid(key),
+        }
+    }
+
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub usage: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+fn encode_coordinate (bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+impl SigningKey {
+
+    // Symmetric keys are secret, so only the asymmetric ones are published
+    pub fn jwk (&self) -> Option<Jwk> {
+        let kid = self.kid()?.to_string();
+        match self {
+            SigningKey::HS256(_) => None,
+            SigningKey::EdDSA(key) => Some(Jwk {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                x: encode_coordinate(&key.public_key().to_bytes()[..]),
+                y: None,
+                kid,
+                alg: "EdDSA".to_string(),
+                usage: "sig".to_string(),
+            }),
+            SigningKey::ES256(key) => {
+                // Uncompressed SEC1 point: 0x04 || x || y
+                let point = ECDSAP256PublicKeyLike::public_key(&key.public_key()).to_bytes_uncompressed();
+                Some(Jwk {
+                    kty: "EC".to_string(),
+                    crv: "P-256".to_string(),
+                    x: encode_coordinate(&point[1..33]),
+                    y: Some(encode_coordinate(&point[33..65])),
+                    kid,
+                    alg: "ES256".to_string(),
+                    usage: "sig".to_string(),
+                })
+            }
+        }
+    }
+
+}
+
+// Keys are ordered from oldest to newest; the newest one signs, all of them verify
+pub struct KeyRing {
+    keys: Vec<SigningKey>,
+}
+
+impl KeyRing {
+
+    pub fn new (entries: &Vec<KeyEntry>) -> Result<KeyRing, Box<dyn Error>> {
+        if entries.is_empty() {
+            return Err("The key ring has no keys".into())
+        }
+
+        Ok(KeyRing {
+            keys: entries.into_iter()
+                .map(|entry| entry.key())
+                .collect::<Result<Vec<SigningKey>, Box<dyn Error>>>()?
+        })
+    }
+
+    pub fn from_config (config: &Config) -> Result<KeyRing, Box<dyn Error>> {
+        if let Ok(secret) = config.get_str("jwt_secret") {
+            return KeyRing::new(&vec![KeyEntry::from_secret("default", secret.as_bytes())])
+        }
+
+        let path = key_file(config);
+        if !path.exists() {
+            println!("\t=> No key file found, generating {}", path.display());
+            save(&path, &vec![KeyEntry::generate(algorithm(config))?])?;
+        }
+
+        KeyRing::new(&load(&path)?)
+    }
+
+    pub fn signing_key (&self) -> &SigningKey {
+        self.keys.last().expect("The key ring has no keys")
+    }
+
+    pub fn find (&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.iter()
+            .find(|key| key.kid() == Some(kid))
+    }
+
+    // Only tokens minted for the same kind of claims pass, whatever else the keys have signed
+    pub fn verify_token<C: Jwt> (&self, token: &str) -> Result<JWTClaims<C>, jwt_simple::Error> {
+        let metadata = JwtToken::decode_metadata(token)?;
+        let kid = metadata.key_id()
+            .ok_or(jwt_simple::Error::msg("Token has no key id"))?;
+
+        let options = VerificationOptions {
+            allowed_audiences: Some(std::iter::once(C::AUDIENCE.to_string()).collect()),
+            ..Default::default()
+        };
+
+        self.find(kid)
+            .ok_or(jwt_simple::Error::msg("Unknown key id"))?
+            .verify::<C>(token, options)
+    }
+
+    pub fn jwks (&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys.iter()
+                .filter_map(|key| key.jwk())
+                .collect()
+        }
+    }
+
+}
+
+pub fn algorithm (config: &Config) -> &str {
+    config.get_str("jwt_algorithm").unwrap_or(DEFAULT_ALGORITHM)
+}
+
+pub fn key_file (config: &Config) -> PathBuf {
+    PathBuf::from(config.get_str("jwt_key_file").unwrap_or(DEFAULT_KEY_FILE))
+}
+
+pub fn load (path: &Path) -> Result<Vec<KeyEntry>, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+// The file holds the private signing keys, only the owner gets to read it
+pub fn save (path: &Path, entries: &Vec<KeyEntry>) -> Result<(), Box<dyn Error>> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // `mode` only applies to new files, older ones may still be world-readable
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(serde_json::to_string_pretty(entries)?.as_bytes())?;
+    Ok(())
+}
+
+// Adds a fresh signing key and retires the oldest ones, so that only `window` keys stay valid
+pub fn rotate (config: &Config) -> Result<KeyEntry, Box<dyn Error>> {
+    let window = config.get_int("jwt_key_window")
+        .map(|window| window as usize)
+        .unwrap_or(DEFAULT_KEY_WINDOW)
+        .max(1);
+
+    let path = key_file(config);
+    let mut entries = if path.exists() { load(&path)? } else { vec![] };
+
+    let entry = KeyEntry::generate(algorithm(config))?;
+    entries.push(entry.clone());
+
+    let retired = entries.len().saturating_sub(window);
+    for old in entries.drain(..retired) {
+        println!("\t=> Retired key {}", old.kid);
+    }
+
+    save(&path, &entries)?;
+    println!("\t=> Signing with key {}", entry.kid);
+
+    Ok(entry)
+}
+
+pub fn retire (config: &Config, kid: &str) -> Result<(), Box<dyn Error>> {
+    let path = key_file(config);
+    let mut entries = load(&path)?;
+
+    if entries.len() == 1 && entries[0].kid == kid {
+        return Err("Cannot retire the only signing key, rotate first".into())
+    }
+
+    let before = entries.len();
+    entries.retain(|entry| entry.kid != kid);
+    if entries.len() == before {
+        return Err(format!("No key with id {}", kid).into())
+    }
+
+    save(&path, &entries)?;
+    println!("\t=> Retired key {}", kid);
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use jwt_simple::prelude::{Claims, Duration, JWTClaims};
+use serde::{Deserialize, Serialize};
+
+use crate::db::DefaultConnection;
+use crate::verification::Blacklist;
+use super::{Jwt, JwtKey, LoginHandler, jwt_data::JwtData, new_jti};
+
+pub const MFA_CHALLENGE_MINUTES: u64 = 5;
+
+// Proves the password was right, but grants nothing until the second factor is posted
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_user_id: i64,
+}
+
+impl Jwt for MfaChallenge {
+
+    const AUDIENCE: &'static str = "contactive:mfa";
+
+    fn encode<Key: JwtKey> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
+        key.sign(
+            Claims::with_custom_claims (
+                self.clone (),
+                Duration::from_mins (MFA_CHALLENGE_MINUTES)
+            ).with_jwt_id (new_jti ())
+                .with_audience (Self::AUDIENCE)
+        )
+    }
+
+}
+
+impl LoginHandler {
+
+    pub fn challenge (&self, user_id: i64) -> Result<String, jwt_simple::Error> {
+        MfaChallenge { mfa_user_id: user_id }
+            .encode (self.keys.signing_key ())
+    }
+
+    pub fn open_challenge (&self, token: &str) -> Result<JWTClaims<MfaChallenge>, jwt_simple::Error> {
+        let claims = self.keys.verify_token::<MfaChallenge> (token)?;
+
+        if self.blacklist.is_blacklisted (&JwtData::id_of (&claims, token)) {
+            return Err(jwt_simple::Error::msg ("Challenge was already used"));
+        }
+
+        Ok(claims)
+    }
+
+    // A challenge is single use once it has been answered correctly
+    pub fn close_challenge (&self, claims: JWTClaims<MfaChallenge>, token: String, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        self.blacklist.blacklist (JwtData::new_from_claims (claims, token), db)
+    }
+
+}
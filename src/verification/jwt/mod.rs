@@ -1,61 +1,92 @@
 use std::error::Error;
 
-use jwt_simple::{prelude::{Claims, Duration, HS256Key, JWTClaims, MACLike}};
-use rocket::{http::Status, request::FromRequest};
+use jwt_simple::{prelude::{Claims, Duration, JWTClaims, VerificationOptions}};
+use rocket::{config::Config, http::Status, request::FromRequest};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-use crate::{db::user::User, routing::ToStatus};
+use crate::{db::{DBPool, DefaultConnection, session::Session, user::User}, routing::ToStatus};
 
-use self::blacklist::ThreadBlacklist;
+use self::blacklist::{PgBlacklist, ThreadBlacklist};
 use self::jwt_data::JwtData;
+use self::keys::KeyRing;
 
-use super::{Blacklist, Verifier};
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::{Blacklist, Verifier, random_token};
+use super::cookie::{SESSION_COOKIE, csrf_passes};
 
 pub mod jwt_data;
 pub mod blacklist;
+pub mod keys;
 pub mod persona_jwt;
+pub mod refresh;
+pub mod mfa;
+
+pub const ACCESS_TOKEN_MINUTES: u64 = 15;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct LoginJwt {
     pub username: String,
     pub user_id: i64,
+    // The refresh token family this access token was issued for
+    pub sid: String,
 }
 
 impl LoginJwt {
 
-    pub fn new (username: String, user_id: i64) -> Self {
+    pub fn new (username: String, user_id: i64, sid: String) -> Self {
         Self {
             username,
-            user_id
+            user_id,
+            sid
         }
     }
 
 }
 
-impl From<User> for LoginJwt {
-    fn from(u: User) -> Self {
+impl From<(User, String)> for LoginJwt {
+    fn from((u, sid): (User, String)) -> Self {
         Self {
             username: u.username,
-            user_id: u.id
+            user_id: u.id,
+            sid
         }
     }
 }
 
+// Anything that can sign and verify tokens, regardless of the algorithm behind it
+pub trait JwtKey {
+
+    fn sign<C: Serialize + DeserializeOwned> (&self, claims: JWTClaims<C>) -> Result<String, jwt_simple::Error>;
+
+    fn verify<C: Serialize + DeserializeOwned> (&self, token: &str, options: VerificationOptions) -> Result<JWTClaims<C>, jwt_simple::Error>;
+
+    fn kid (&self) -> Option<&str>;
+
+}
+
 pub trait Jwt: Clone + Serialize + DeserializeOwned + PartialEq {
 
-    fn encode<Key: MACLike> (&self, key: &Key) -> Result<String, jwt_simple::Error>;
+    // Every kind of token is signed by the same keys, the audience keeps one from passing for another
+    const AUDIENCE: &'static str;
+
+    fn encode<Key: JwtKey> (&self, key: &Key) -> Result<String, jwt_simple::Error>;
 
 }
 
+pub fn new_jti () -> String {
+    random_token (16)
+}
+
 impl Jwt for LoginJwt {
 
-    fn encode<Key: MACLike> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
-        key.authenticate(
+    const AUDIENCE: &'static str = "contactive:login";
+
+    fn encode<Key: JwtKey> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
+        key.sign(
             Claims::with_custom_claims (
                 self.clone (),
-                Duration::from_hours (2)
-            )
+                Duration::from_mins (ACCESS_TOKEN_MINUTES)
+            ).with_jwt_id (new_jti ())
+                .with_audience (Self::AUDIENCE)
         )
     }
 
@@ -75,30 +106,57 @@ pub trait JwtHandler<Source, J: Jwt>: Verifier<Ok = JWTClaims<J>, Err = jwt_simp
 }
 
 pub struct LoginHandler {
-    pub key: HS256Key,
-    pub blacklist: ThreadBlacklist,
+    pub keys: KeyRing,
+    pub blacklist: Box<dyn Blacklist<Data = JwtData>>,
+    // Session cookies are only sent over HTTPS unless turned off for development
+    pub secure_cookies: bool,
+    pool: DBPool,
 }
 
 impl LoginHandler {
 
-    pub fn new () -> Self {
+    pub fn new (config: &Config, pool: &DBPool) -> Self {
+        let blacklist: Box<dyn Blacklist<Data = JwtData>> = match config.get_str ("token_blacklist") {
+            Ok("memory") => Box::new (ThreadBlacklist::new ()),
+            _ => Box::new (PgBlacklist::new (pool.clone ())),
+        };
+
         Self {
-            key: HS256Key::from_bytes("abcd".as_bytes()),
-            blacklist: ThreadBlacklist::new (),
+            keys: KeyRing::from_config (config)
+                .expect ("Could not load the JWT signing keys"),
+            blacklist,
+            secure_cookies: config.get_bool ("session_cookie_secure").unwrap_or (true),
+            pool: pool.clone (),
         }
     }
 
 }
 
+impl LoginHandler {
+
+    pub fn verify_session (&self, token: &Token, db: &DefaultConnection) -> Result<JWTClaims<LoginJwt>, jwt_simple::Error> {
+        println!("\t=> Verifying for token: {}", token.0);
+        let claims = self.extract(&token.0)?;
+
+        if !Session::is_active (&claims.custom.sid, db)? {
+            return Err(jwt_simple::Error::msg ("Session has been signed out"));
+        }
+        Session::touch (&claims.custom.sid, db)?;
+
+        Ok(claims)
+    }
+
+}
+
 impl JwtHandler<&String, LoginJwt> for LoginHandler {
 
     fn extract (&self, token: &String) -> Result<JWTClaims<LoginJwt>, jwt_simple::Error> {
-        let claims = match self.key.verify_token::<LoginJwt> (token, None) {
+        let claims = match self.keys.verify_token::<LoginJwt> (token) {
             Ok(claims) => claims,
             Err(err) => return Err(err)
         };
         
-        if self.blacklist.is_blacklisted (token) {
+        if self.blacklist.is_blacklisted (&JwtData::id_of (&claims, token)) {
             return Err(jwt_simple::Error::msg ("Token is blacklisted"));
         };
 
@@ -119,12 +177,12 @@ impl<'a> Blacklist for LoginHandler {
 
     type Data = JwtData;
 
-    fn blacklist (&self, data: Self::Data) {
-        self.blacklist.blacklist(data)
+    fn blacklist (&self, data: Self::Data, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        self.blacklist.blacklist(data, db)
     }
 
-    fn is_blacklisted (&self, token: &String) -> bool {
-        self.blacklist.is_blacklisted(token)
+    fn is_blacklisted (&self, id: &str) -> bool {
+        self.blacklist.is_blacklisted(id)
     }
 }
 
@@ -136,17 +194,23 @@ impl<'a, 'r> FromRequest<'a, 'r> for Token {
     type Error = jwt_simple::Error;
 
     fn from_request(request: &'a rocket::Request<'r>) -> rocket::request::Outcome<Self, Self::Error> {
-        match request.headers().get(AUTH_HEADER_NAME).next() {
-            Some(auth) => rocket::request::Outcome::Success(Token({
-                let s = auth.to_string();
-                if s.starts_with("Basic ") {
-                    s[6..].to_string()
-                } else {
-                    s
-                }
-            })),
-            None => rocket::request::Outcome::Failure((Status::Unauthorized, jwt_simple::Error::msg("Unauthorized"),))
+        if let Some(auth) = request.headers().get_one(AUTH_HEADER_NAME) {
+            let token = auth.strip_prefix("Bearer ").unwrap_or(auth);
+            return rocket::request::Outcome::Success(Token(token.to_string()))
         }
+
+        // Browsers hold the token in the session cookie instead
+        let mut cookies = request.cookies();
+        let session = match cookies.get_private(SESSION_COOKIE) {
+            Some(session) => session,
+            None => return rocket::request::Outcome::Failure((Status::Unauthorized, jwt_simple::Error::msg("Unauthorized")))
+        };
+
+        if !csrf_passes(request, &mut cookies) {
+            return rocket::request::Outcome::Failure((Status::Forbidden, jwt_simple::Error::msg("Missing or mismatched CSRF token")))
+        }
+
+        rocket::request::Outcome::Success(Token(session.value().to_string()))
     }
 }
 
@@ -162,35 +226,19 @@ impl Verifier for LoginHandler {
 
     type Destination = String;
 
-    fn reauthorize (&self, token: &Token, out: &mut String) -> Result<(), Box<dyn Error>> {
-        let claims = self.extract(&token.0)?;
-        self.authorize(out, claims.custom)
+    fn reauthorize (&self, _: &Token, _: &mut String) -> Result<(), Box<dyn Error>> {
+        Err("Access tokens are renewed with a refresh token".into())
     }
 
     fn verify (&self, token: &Token) -> Result<JWTClaims<LoginJwt>, jwt_simple::Error> {
-        println!("\t=> Verifying for token: {}", token.0);
-        self.extract(&token.0).and_then(|claims| {
-            let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect ("Time went backwards")
-                    .as_secs();
-            let expiration = match claims.expires_at {
-                Some(claims) => claims,
-                None => return Err(jwt_simple::Error::msg("Invalid JWT"))
-            }.as_secs() - 10 * 60;
-            println!("\t=> now - expiration = {}", now - expiration);
-            if now > expiration {
-                Err(jwt_simple::Error::msg("token should be renewed"))
-            } else {
-                Ok(claims)
-            }
-        })
+        let db = self.pool.get ()?;
+        self.verify_session (token, &db)
     }
 
     fn authorize<G> (&self, key: &mut String, userdata: G) -> Result<(), Box<dyn Error>>
         where <Self as Verifier>::Data: From<G>
     {
-        *key = LoginJwt::from (userdata).encode (&self.key)?;
+        *key = LoginJwt::from (userdata).encode (self.keys.signing_key ())?;
 
         println! ("\t=> {}", key);
         
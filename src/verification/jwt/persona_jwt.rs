@@ -1,29 +1,203 @@
-use jwt_simple::prelude::{Claims, Duration, HS256Key, JWTClaims, MACLike};
+use jwt_simple::prelude::{Claims, Duration, JWTClaims};
+use rocket::{config::Config, http::Status};
 use serde::{Deserialize, Serialize};
 use crate::verification::{Blacklist, Verifier};
 
-use super::{Jwt, JwtHandler};
-use crate::db::contact::{Contact, UserContactRelation};
-use crate::db::{DBState, Register};
-use crate::db::user::User;
+use super::{Jwt, JwtHandler, JwtKey, jwt_data::JwtData, keys::KeyRing, new_jti};
+use crate::db::contact::{Contact, ContactPermission, IsContact, UserContactRelation};
+use crate::db::{DBConnection, DBPool, DefaultConnection, Register};
+use crate::db::share_link::ShareLink;
+use crate::db::persona::{Persona, PersonaToken};
+use crate::db::user::{ForUser, User, UserId};
+use crate::routing::ToStatus;
+use diesel::{Connection, OptionalExtension};
 use std::error::Error;
 
+pub const SHARE_LINK_DEFAULT_HOURS: u64 = 24;
+
+// A named claim, since the custom claims are flattened into the token's payload
 #[derive(Clone, Serialize, Deserialize, PartialEq)]
-pub struct ContactJwt(pub i64);
+pub struct ContactJwt {
+    pub shared_contact_id: i64
+}
 
 impl Jwt for ContactJwt {
-    fn encode<Key: jwt_simple::prelude::MACLike> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
-        key.authenticate(
+    const AUDIENCE: &'static str = "contactive:share-link";
+
+    fn encode<Key: JwtKey> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
+        key.sign(
+            Claims::with_custom_claims (
+                self.clone (),
+                Duration::from_hours (SHARE_LINK_DEFAULT_HOURS)
+            ).with_jwt_id (new_jti ())
+                .with_audience (Self::AUDIENCE)
+        )
+    }
+}
+
+pub const PERSONA_TOKEN_DEFAULT_HOURS: u64 = 24 * 7;
+
+// Hands out a persona's card; like links, each token has a row in `persona_tokens` so it can be revoked
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
+pub struct PersonaJwt {
+    pub persona_id: i64,
+    // Only this user may open the token, required for private personas
+    pub recipient_id: Option<i64>,
+}
+
+impl Jwt for PersonaJwt {
+    const AUDIENCE: &'static str = "contactive:persona";
+
+    fn encode<Key: JwtKey> (&self, key: &Key) -> Result<String, jwt_simple::Error> {
+        key.sign(
             Claims::with_custom_claims (
                 self.clone (),
-                Duration::from_days (1)
-            )
+                Duration::from_hours (PERSONA_TOKEN_DEFAULT_HOURS)
+            ).with_jwt_id (new_jti ())
+                .with_audience (Self::AUDIENCE)
         )
     }
 }
 
+#[derive(Debug)]
+pub enum ShareLinkError {
+    // Forged, expired, revoked or used up
+    Invalid,
+    NotRecipient,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ShareLinkError {
+    fn from(e: diesel::result::Error) -> Self {
+        ShareLinkError::Database(e)
+    }
+}
+
+impl ToStatus for ShareLinkError {
+    fn to_status (&self) -> Status {
+        match self {
+            ShareLinkError::Invalid => Status::NotFound,
+            ShareLinkError::NotRecipient => Status::Forbidden,
+            ShareLinkError::Database(e) => e.to_status(),
+        }
+    }
+}
+
+// Links are signed with the same keys as logins; their state lives in `share_links`
 pub struct ContactJwtHandler {
-    pub key: HS256Key
+    pub keys: KeyRing,
+    pool: DBPool,
+}
+
+impl ContactJwtHandler {
+
+    pub fn new (config: &Config, pool: &DBPool) -> Self {
+        Self {
+            keys: KeyRing::from_config (config)
+                .expect ("Could not load the JWT signing keys"),
+            pool: pool.clone (),
+        }
+    }
+
+    pub fn mint (&self, contact_id: i64, valid_for: Duration) -> Result<(String, JWTClaims<ContactJwt>), jwt_simple::Error> {
+        let claims = Claims::with_custom_claims (
+            ContactJwt { shared_contact_id: contact_id },
+            valid_for
+        ).with_jwt_id (new_jti ())
+            .with_audience (ContactJwt::AUDIENCE);
+
+        let token = self.keys.signing_key ().sign (claims.clone ())?;
+        Ok((token, claims))
+    }
+
+    pub fn mint_persona (&self, persona: PersonaJwt, valid_for: Duration) -> Result<(String, JWTClaims<PersonaJwt>), jwt_simple::Error> {
+        let claims = Claims::with_custom_claims (persona, valid_for)
+            .with_jwt_id (new_jti ())
+            .with_audience (PersonaJwt::AUDIENCE);
+
+        let token = self.keys.signing_key ().sign (claims.clone ())?;
+        Ok((token, claims))
+    }
+
+    // The persona has to still exist, and must not have turned private since an open token was made
+    pub fn open_persona (&self, token: &str, user: UserId, db: &DefaultConnection) -> Result<Persona, ShareLinkError> {
+        let claims = self.keys.verify_token::<PersonaJwt> (token)
+            .map_err (|_| ShareLinkError::Invalid)?;
+        let jti = claims.jwt_id.clone ()
+            .ok_or (ShareLinkError::Invalid)?;
+
+        let issued = match PersonaToken::query_by_id (&jti, db) {
+            Ok(issued) => issued,
+            Err(diesel::result::Error::NotFound) => return Err(ShareLinkError::Invalid),
+            Err(e) => return Err(e.into ())
+        };
+
+        if !issued.is_usable () || issued.persona_id != claims.custom.persona_id {
+            return Err(ShareLinkError::Invalid)
+        }
+
+        if claims.custom.recipient_id.map (|recipient| recipient != *user).unwrap_or (false) {
+            return Err(ShareLinkError::NotRecipient)
+        }
+
+        let persona = match Persona::force_get_by_id (claims.custom.persona_id, db) {
+            Ok(persona) => persona,
+            Err(diesel::result::Error::NotFound) => return Err(ShareLinkError::Invalid),
+            Err(e) => return Err(e.into ())
+        };
+
+        if persona.private && claims.custom.recipient_id.is_none () {
+            return Err(ShareLinkError::Invalid)
+        }
+
+        Ok(persona)
+    }
+
+    // A use is only counted when the contact wasn't in the user's address book already
+    pub fn accept (&self, token: &str, user: UserId, db: &DefaultConnection) -> Result<(Contact, bool), ShareLinkError> {
+        // The locked row below is checked instead of the blacklist, which would take a second connection
+        let claims = self.keys.verify_token::<ContactJwt> (token)
+            .map_err (|_| ShareLinkError::Invalid)?;
+        let jti = claims.jwt_id.clone ()
+            .ok_or (ShareLinkError::Invalid)?;
+
+        db.transaction::<_, ShareLinkError, _>(|| {
+            let link = match ShareLink::lock (&jti, db) {
+                Ok(link) => link,
+                Err(diesel::result::Error::NotFound) => return Err(ShareLinkError::Invalid),
+                Err(e) => return Err(e.into ())
+            };
+
+            if !link.is_usable () || link.contact_id != claims.custom.shared_contact_id {
+                return Err(ShareLinkError::Invalid)
+            }
+
+            if link.recipient_id.map (|recipient| recipient != *user).unwrap_or (false) {
+                return Err(ShareLinkError::NotRecipient)
+            }
+
+            let contact = Contact::force_get_by_id (link.contact_id, db)?;
+            // Links made before the contact was turned local die with it
+            if !contact.visibility ().can_share () {
+                return Err(ShareLinkError::Invalid)
+            }
+
+            if ForUser::<Contact>::from (user).has_jurisdiction (contact.id, ContactPermission::Viewer, db).optional ()?.is_some () {
+                return Ok((contact, false))
+            }
+
+            ShareLink::count_use (&jti, db)?;
+            // Links only ever hand out read access
+            UserContactRelation (
+                *user,
+                contact.id,
+                ContactPermission::Viewer.into ()
+            ).register (db)?;
+
+            Ok((contact, true))
+        })
+    }
+
 }
 
 impl JwtHandler<&String, ContactJwt> for ContactJwtHandler {
@@ -37,42 +211,56 @@ impl Verifier for ContactJwtHandler {
     type Ok = JWTClaims<ContactJwt>;
     type Err = jwt_simple::Error;
     type Source = String;
-    type Destination = (User, DBState);
+    type Destination = (User, DBConnection);
 
-    fn reauthorize(&self, source: &String, destination: &mut (User, DBState)) -> Result<(), Box<dyn Error>> {
-        let (_, db) = destination;
-        let claims = self.verify(source)?;
-        let contact = Contact::force_get_by_id(claims.custom.0, db)?;
-        self.authorize(destination, contact)
+    fn reauthorize(&self, source: &String, destination: &mut (User, DBConnection)) -> Result<(), Box<dyn Error>> {
+        let (user, db) = destination;
+        self.accept(source, UserId::new(user.id), db)
+            .map_err(|e| format!("{:?}", e))?;
+        Ok(())
     }
 
     fn verify (&self, token: &String) -> Result<Self::Ok, Self::Err> {
-        self.key.verify_token::<ContactJwt> (token, None)
+        let claims = self.keys.verify_token::<ContactJwt> (token)?;
+
+        if self.is_blacklisted (&JwtData::id_of (&claims, token)) {
+            return Err(jwt_simple::Error::msg ("Share link is no longer usable"));
+        }
+
+        Ok(claims)
     }
 
-    fn authorize<G> (&self, (user, db): &mut (User, DBState), item: G) -> Result<(), Box<dyn Error>>
+    fn authorize<G> (&self, (user, db): &mut (User, DBConnection), item: G) -> Result<(), Box<dyn Error>>
         where Contact: From<G>
     {
         let contact = Contact::from(item);
 
         UserContactRelation (
             user.id,
-            contact.id
+            contact.id,
+            ContactPermission::Viewer.into ()
         ).register (db)?;
 
         Ok(())
     }
 }
 
-// No blacklist
+// Revocations go straight to the link's row, which every instance reads
 impl Blacklist for ContactJwtHandler {
-    type Data = ContactJwt;
+    type Data = JwtData;
 
-    fn blacklist (&self, _: Self::Data) {
-        
+    fn blacklist (&self, data: Self::Data, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        ShareLink::mark_revoked (&data.jti, db)?;
+        Ok(())
     }
 
-    fn is_blacklisted (&self, _: &String) -> bool {
-        false
+    // Anything that can't be looked up is treated as revoked
+    fn is_blacklisted (&self, id: &str) -> bool {
+        match self.pool.get () {
+            Ok(db) => ShareLink::query_by_id (id, &db)
+                .map (|link| !link.is_usable ())
+                .unwrap_or (true),
+            Err(_) => true
+        }
     }
-}
\ No newline at end of file
+}
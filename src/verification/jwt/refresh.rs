@@ -0,0 +1,125 @@
+use std::error::Error;
+
+use chrono::Utc;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{DefaultConnection, QueryById, Register, session::{NewSession, Session}, token::{NewRefreshToken, RefreshToken}, user::User};
+use crate::routing::ToStatus;
+use crate::verification::{ClientInfo, Verifier, fingerprint, random_token};
+
+use super::{ACCESS_TOKEN_MINUTES, LoginHandler};
+
+pub const REFRESH_TOKEN_DAYS: i64 = 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug)]
+pub enum RefreshError {
+    Invalid,
+    Reused,
+    Suspended,
+    Database(diesel::result::Error),
+    Signing(Box<dyn Error>),
+}
+
+impl From<diesel::result::Error> for RefreshError {
+    fn from(e: diesel::result::Error) -> Self {
+        RefreshError::Database(e)
+    }
+}
+
+impl ToStatus for RefreshError {
+    fn to_status (&self) -> Status {
+        match self {
+            RefreshError::Invalid => Status::Unauthorized,
+            RefreshError::Reused => Status::Unauthorized,
+            RefreshError::Suspended => Status::Forbidden,
+            RefreshError::Database(e) => e.to_status(),
+            RefreshError::Signing(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl LoginHandler {
+
+    pub fn issue (&self, user: User, client: &ClientInfo, db: &DefaultConnection) -> Result<TokenPair, RefreshError> {
+        if user.suspended_at.is_some () {
+            return Err(RefreshError::Suspended)
+        }
+
+        let session = NewSession {
+            id: random_token (16),
+            user_id: user.id,
+            user_agent: client.user_agent.clone (),
+            ip: client.ip.clone ()
+        }.register (db)?;
+
+        self.issue_in_family (user, session.id, db)
+    }
+
+    fn issue_in_family (&self, user: User, family: String, db: &DefaultConnection) -> Result<TokenPair, RefreshError> {
+        let secret = random_token (32);
+
+        NewRefreshToken {
+            token_hash: fingerprint (&secret),
+            family: family.clone (),
+            user_id: user.id,
+            expires: Utc::now().naive_utc() + chrono::Duration::days (REFRESH_TOKEN_DAYS)
+        }.register (db)?;
+
+        let mut access_token = String::new ();
+        self.authorize (&mut access_token, (user, family))
+            .map_err (RefreshError::Signing)?;
+
+        Ok(TokenPair {
+            access_token,
+            refresh_token: secret,
+            expires_in: ACCESS_TOKEN_MINUTES * 60
+        })
+    }
+
+    // Refresh tokens are single use: presenting one that was already exchanged revokes its whole family
+    pub fn refresh (&self, secret: &str, client: &ClientInfo, db: &DefaultConnection) -> Result<TokenPair, RefreshError> {
+        let token = match RefreshToken::query_by_hash (&fingerprint (secret), db) {
+            Ok(token) => token,
+            Err(diesel::result::Error::NotFound) => return Err(RefreshError::Invalid),
+            Err(e) => return Err(e.into ())
+        };
+
+        if !token.is_valid () || !Session::is_active (&token.family, db)? {
+            return Err(RefreshError::Invalid)
+        }
+
+        let exchanged = if token.rotated {
+            Err(diesel::result::Error::NotFound)
+        } else {
+            token.mark_rotated (db)
+        };
+
+        match exchanged {
+            Ok(()) => {},
+            Err(diesel::result::Error::NotFound) => {
+                println!("\t=> Refresh token reuse detected, revoking family {}", token.family);
+                Session::revoke (&token.family, db)?;
+                return Err(RefreshError::Reused)
+            },
+            Err(e) => return Err(e.into ())
+        }
+
+        Session::renew (&token.family, client.user_agent.clone (), client.ip.clone (), db)?;
+
+        let user = User::query_by_id (token.user_id, db)?;
+        if user.suspended_at.is_some () {
+            return Err(RefreshError::Suspended)
+        }
+
+        self.issue_in_family (user, token.family, db)
+    }
+
+}
@@ -1,13 +1,95 @@
+use crate::db::DefaultConnection;
 use crate::routing::ToStatus;
 use std::error::Error;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+use rand::RngCore;
+use rocket::{Request, config::Config};
+use sha2::{Digest, Sha256};
 
 pub mod jwt;
+pub mod totp;
+pub mod access_token;
+pub mod permission;
+pub mod throttle;
+pub mod cookie;
+
+// The width of the `user_agent` columns, anything longer is cut off
+pub const MAX_USER_AGENT_CHARS: usize = 512;
+
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+
+    pub fn user_agent_of (header: &str) -> String {
+        header.chars ().take (MAX_USER_AGENT_CHARS).collect ()
+    }
+
+    // Addresses in the same /24 or /48 are treated as one network
+    pub fn network (&self) -> Option<String> {
+        match self.ip.as_ref ()?.parse::<IpAddr> ().ok ()? {
+            IpAddr::V4(ip) => {
+                let [a, b, c, _] = ip.octets ();
+                Some(format! ("{}.{}.{}.0/24", a, b, c))
+            },
+            IpAddr::V6(ip) => {
+                let segments = ip.segments ();
+                Some(format! ("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2]))
+            }
+        }
+    }
+
+}
+
+lazy_static! {
+    static ref TRUSTED_PROXIES: RwLock<Vec<IpAddr>> = RwLock::new (Vec::new ());
+}
+
+// `X-Real-IP` is only believed when the request comes straight from one of these
+pub fn configure_proxies (config: &Config) {
+    let proxies = config.get_slice ("trusted_proxies")
+        .map (|proxies| proxies.iter ()
+            .filter_map (|proxy| proxy.as_str ()?.parse::<IpAddr> ().ok ())
+            .collect ())
+        .unwrap_or_default ();
+
+    *TRUSTED_PROXIES.write ().unwrap () = proxies;
+}
+
+pub fn client_ip (request: &Request) -> Option<IpAddr> {
+    let remote = request.remote ()?.ip ();
+
+    if TRUSTED_PROXIES.read ().unwrap ().contains (&remote) {
+        return request.real_ip ().or (Some(remote))
+    }
+
+    Some(remote)
+}
+
+pub fn random_token (bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    rand::thread_rng ().fill_bytes (&mut buffer);
+    base64::encode_config (&buffer, base64::URL_SAFE_NO_PAD)
+}
+
+// Opaque secrets are only ever stored as their fingerprint
+pub fn fingerprint (secret: &str) -> String {
+    let mut engine = Sha256::new();
+    engine.update (secret);
+    base64::encode_config (&engine.finalize()[..], base64::URL_SAFE_NO_PAD)
+}
 pub trait Blacklist: Send + Sync {
 
     type Data;
 
-    fn blacklist (&self, data: Self::Data);
-    fn is_blacklisted (&self, token: &String) -> bool;
+    // Takes the caller's connection, a revocation that could not be saved has to fail the request
+    fn blacklist (&self, data: Self::Data, db: &DefaultConnection) -> Result<(), diesel::result::Error>;
+    fn is_blacklisted (&self, id: &str) -> bool;
 
 }
 
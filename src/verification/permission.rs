@@ -0,0 +1,24 @@
+// Permissions are plain names in `role_permissions`, each one gets a marker type for the `Authorized` guard
+pub trait Permission {
+    const NAME: &'static str;
+}
+
+#[macro_export]
+macro_rules! permission {
+    ($marker:ident => $name:expr) => {
+        pub struct $marker;
+
+        impl crate::verification::permission::Permission for $marker {
+            const NAME: &'static str = $name;
+        }
+    };
+}
+
+permission! (ReadRoles => "roles:read");
+permission! (AssignRoles => "roles:assign");
+permission! (DeleteUsers => "users:delete");
+permission! (ReadUsers => "users:read");
+permission! (SuspendUsers => "users:suspend");
+permission! (ResetPasswords => "users:reset_password");
+permission! (UnlockUsers => "users:unlock");
+permission! (ReadAudit => "audit:read");
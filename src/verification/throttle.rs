@@ -0,0 +1,185 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+use chrono::{NaiveDateTime, Utc};
+use rocket::config::Config;
+
+use crate::db::{DefaultConnection, user::User};
+
+pub const DEFAULT_ACCOUNT_FREE_ATTEMPTS: u32 = 5;
+// Addresses get more leeway, many users can share one behind a NAT
+pub const DEFAULT_ADDRESS_FREE_ATTEMPTS: u32 = 20;
+pub const DEFAULT_MAX_LOCKOUT_SECS: u64 = 15 * 60;
+// Addresses that stay quiet for this long start over from zero
+pub const FORGET_AFTER_SECS: u64 = 60 * 60;
+// Past this many addresses the one that failed longest ago is dropped first
+pub const MAX_TRACKED_ADDRESSES: usize = 100_000;
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    blocked_until: Option<Instant>,
+}
+
+pub struct Throttle {
+    account_free_attempts: u32,
+    address_free_attempts: u32,
+    max_lockout: u64,
+    addresses: Mutex<HashMap<String, Attempts>>,
+}
+
+impl Throttle {
+
+    pub fn new (config: &Config) -> Throttle {
+        let get = |key: &str, default: u64| config.get_int (key)
+            .map (|value| value.max (1) as u64)
+            .unwrap_or (default);
+
+        Throttle {
+            account_free_attempts: get ("login_account_free_attempts", DEFAULT_ACCOUNT_FREE_ATTEMPTS as u64) as u32,
+            address_free_attempts: get ("login_address_free_attempts", DEFAULT_ADDRESS_FREE_ATTEMPTS as u64) as u32,
+            max_lockout: get ("login_max_lockout_seconds", DEFAULT_MAX_LOCKOUT_SECS),
+            addresses: Mutex::new (HashMap::new ()),
+        }
+    }
+
+    // Seconds to wait after `failures` failed attempts: nothing at first, then doubling up to the maximum
+    fn backoff (&self, failures: u32, free_attempts: u32) -> Option<u64> {
+        if failures < free_attempts {
+            return None
+        }
+
+        let doublings = (failures - free_attempts).min (32);
+        Some((1u64 << doublings).min (self.max_lockout))
+    }
+
+    // How long the address still has to wait, if it is blocked
+    pub fn check_address (&self, address: &Option<String>) -> Result<(), u64> {
+        let address = match address {
+            Some(address) => address,
+            None => return Ok(())
+        };
+
+        let now = Instant::now ();
+        match self.addresses.lock ().unwrap ().get (address).and_then (|attempts| attempts.blocked_until) {
+            Some(until) if until > now => Err((until - now).as_secs ().max (1)),
+            _ => Ok(())
+        }
+    }
+
+    pub fn address_failed (&self, address: &Option<String>) {
+        let address = match address {
+            Some(address) => address,
+            None => return
+        };
+
+        let now = Instant::now ();
+        let mut addresses = self.addresses.lock ().unwrap ();
+        addresses.retain (|_, attempts| now - attempts.last_failure < Duration::from_secs (FORGET_AFTER_SECS));
+
+        if addresses.len () >= MAX_TRACKED_ADDRESSES && !addresses.contains_key (address) {
+            let oldest = addresses.iter ()
+                .min_by_key (|(_, attempts)| attempts.last_failure)
+                .map (|(address, _)| address.clone ());
+
+            if let Some(oldest) = oldest {
+                addresses.remove (&oldest);
+            }
+        }
+
+        let attempts = addresses.entry (address.clone ()).or_insert (Attempts {
+            failures: 0,
+            last_failure: now,
+            blocked_until: None,
+        });
+
+        attempts.failures += 1;
+        attempts.last_failure = now;
+        attempts.blocked_until = self.backoff (attempts.failures, self.address_free_attempts)
+            .map (|secs| now + Duration::from_secs (secs));
+    }
+
+    pub fn check_account (&self, user: &User) -> Result<(), u64> {
+        let now = Utc::now ().naive_utc ();
+        match user.locked_until {
+            Some(until) if until > now => Err((until - now).num_seconds ().max (1) as u64),
+            _ => Ok(())
+        }
+    }
+
+    pub fn account_failed (&self, user: &User, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        let failures = User::record_failed_login (user.id, db)?;
+
+        if let Some(secs) = self.backoff (failures as u32, self.account_free_attempts) {
+            let until: NaiveDateTime = Utc::now ().naive_utc () + chrono::Duration::seconds (secs as i64);
+            User::lock (user.id, until, db)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn account_succeeded (&self, user: &User, db: &DefaultConnection) -> Result<(), diesel::result::Error> {
+        if user.failed_logins > 0 || user.locked_until.is_some () {
+            User::unlock (user.id, db)?;
+        }
+
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn throttle () -> Throttle {
+        Throttle {
+            account_free_attempts: 3,
+            address_free_attempts: 3,
+            max_lockout: 60,
+            addresses: Mutex::new (HashMap::new ()),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum () {
+        let throttle = throttle ();
+
+        assert_eq!(throttle.backoff (0, 3), None);
+        assert_eq!(throttle.backoff (2, 3), None);
+        assert_eq!(throttle.backoff (3, 3), Some(1));
+        assert_eq!(throttle.backoff (4, 3), Some(2));
+        assert_eq!(throttle.backoff (8, 3), Some(32));
+        assert_eq!(throttle.backoff (9, 3), Some(60));
+        // Far past the free attempts the shift must not overflow
+        assert_eq!(throttle.backoff (u32::MAX, 3), Some(60));
+    }
+
+    #[test]
+    fn addresses_are_blocked_after_their_free_attempts () {
+        let throttle = throttle ();
+        let address = Some("203.0.113.7".to_string ());
+
+        for _ in 0..2 {
+            throttle.address_failed (&address);
+            assert_eq!(throttle.check_address (&address), Ok(()));
+        }
+
+        throttle.address_failed (&address);
+        assert_eq!(throttle.check_address (&address), Err(1));
+        assert_eq!(throttle.check_address (&Some("203.0.113.8".to_string ())), Ok(()));
+    }
+
+    #[test]
+    fn unknown_addresses_are_never_blocked () {
+        let throttle = throttle ();
+
+        for _ in 0..10 {
+            throttle.address_failed (&None);
+        }
+
+        assert_eq!(throttle.check_address (&None), Ok(()));
+        assert!(throttle.addresses.lock ().unwrap ().is_empty ());
+    }
+
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::{Rng, RngCore, distributions::Alphanumeric};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::db::{DefaultConnection, mfa::{RecoveryCode, UserMfa}, user::User};
+use super::fingerprint;
+
+pub const ISSUER: &str = "Contactive";
+pub const DIGITS: usize = 6;
+pub const STEP: u64 = 30;
+// Codes from one step before or after the current one are still accepted
+pub const SKEW: u64 = 1;
+pub const RECOVERY_CODES: usize = 10;
+pub const RECOVERY_CODE_LENGTH: usize = 10;
+
+pub fn generate_secret () -> String {
+    let mut buffer = vec![0u8; 20];
+    rand::thread_rng ().fill_bytes (&mut buffer);
+    match Secret::Raw(buffer).to_encoded () {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!()
+    }
+}
+
+pub fn totp (secret: &str, user: &User) -> Result<TOTP, Box<dyn Error>> {
+    Ok(TOTP::new (
+        Algorithm::SHA1,
+        DIGITS,
+        SKEW as u8,
+        STEP,
+        Secret::Encoded(secret.to_string ()).to_bytes ()?,
+        Some(ISSUER.to_string ()),
+        user.email.clone ()
+    )?)
+}
+
+// The time step the code belongs to, if it belongs to any within the skew
+fn matching_step (totp: &TOTP, code: &str) -> Result<Option<u64>, Box<dyn Error>> {
+    let current = SystemTime::now ().duration_since (UNIX_EPOCH)?.as_secs () / STEP;
+
+    Ok((current.saturating_sub (SKEW)..=current + SKEW)
+        .find (|step| totp.generate (step * STEP) == code))
+}
+
+pub fn generate_recovery_codes () -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map (|_| rand::thread_rng ()
+            .sample_iter (&Alphanumeric)
+            .take (RECOVERY_CODE_LENGTH)
+            .map (|c| (c as char).to_ascii_lowercase ())
+            .collect ())
+        .collect ()
+}
+
+pub fn recovery_code_hash (code: &str) -> String {
+    fingerprint (&code.trim ().to_ascii_lowercase ())
+}
+
+// Accepts either a fresh TOTP code or an unused recovery code, burning whichever was used
+pub fn check_code (user: &User, mfa: &UserMfa, code: &str, db: &DefaultConnection) -> Result<bool, Box<dyn Error>> {
+    let code = code.trim ();
+
+    if code.len () == DIGITS && code.chars ().all (|c| c.is_ascii_digit ()) {
+        return match matching_step (&totp (&mfa.secret, user)?, code)? {
+            Some(step) => Ok(UserMfa::use_step (user.id, step as i64, db)?),
+            None => Ok(false)
+        }
+    }
+
+    Ok(mfa.confirmed && RecoveryCode::consume (user.id, &recovery_code_hash (code), db)?)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn user () -> User {
+        User {
+            id: 1,
+            username: "alice".to_string (),
+            email: "alice@example.com".to_string (),
+            password: String::new (),
+            email_verified: true,
+            role: "user".to_string (),
+            suspended_at: None,
+            failed_logins: 0,
+            locked_until: None
+        }
+    }
+
+    fn now () -> u64 {
+        SystemTime::now ().duration_since (UNIX_EPOCH).unwrap ().as_secs ()
+    }
+
+    #[test]
+    fn codes_within_the_skew_match_their_step () {
+        let totp = totp (&generate_secret (), &user ()).unwrap ();
+        let current = now () / STEP;
+
+        for step in current - SKEW..=current + SKEW {
+            assert_eq!(matching_step (&totp, &totp.generate (step * STEP)).unwrap (), Some(step));
+        }
+    }
+
+    #[test]
+    fn codes_outside_the_skew_are_rejected () {
+        let totp = totp (&generate_secret (), &user ()).unwrap ();
+        let current = now () / STEP;
+        let stale = totp.generate ((current - SKEW - 1) * STEP);
+
+        // A stale code can collide with a current one by chance, only check it when it doesn't
+        if (current - SKEW..=current + SKEW).all (|step| totp.generate (step * STEP) != stale) {
+            assert_eq!(matching_step (&totp, &stale).unwrap (), None);
+        }
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_lowercase_and_sized () {
+        let codes = generate_recovery_codes ();
+
+        assert_eq!(codes.len (), RECOVERY_CODES);
+        for code in &codes {
+            assert_eq!(code.len (), RECOVERY_CODE_LENGTH);
+            assert!(code.chars ().all (|c| c.is_ascii_lowercase () || c.is_ascii_digit ()));
+        }
+
+        let mut unique = codes.clone ();
+        unique.sort ();
+        unique.dedup ();
+        assert_eq!(unique.len (), codes.len ());
+    }
+
+    #[test]
+    fn recovery_code_hash_ignores_case_and_padding () {
+        let hash = recovery_code_hash ("abcde12345");
+
+        assert_eq!(recovery_code_hash (" ABCDE12345\n"), hash);
+        assert_ne!(recovery_code_hash ("abcde12346"), hash);
+    }
+}